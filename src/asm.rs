@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::disasm::ORIGIN;
+use crate::instruction::{Address, Instruction, Register};
+
+/*
+Assembler for the mnemonics in the Instruction comments, the same syntax the
+disassembler prints, so a listing assembles back into the ROM it came from.
+
+    ; comment
+    SPRITE_HEIGHT equ 5         ; constants, also NAME = value
+    start:                      ; labels
+        LD I, digit
+        DRW V0, V1, SPRITE_HEIGHT
+        JP start
+    digit:
+        db 0xF0, 0x90, #90, 0b10010000, 240
+        dw 0x1234
+        include "more.asm"      ; relative to the including file
+
+Numbers are decimal, 0x/# hex or 0b binary. Operands can add and subtract
+numbers, labels and constants: `LD I, table + 5`.
+*/
+
+// How deep includes may nest before we assume a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// Assemble source text. Includes are looked up relative to the working directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new();
+    assembler.read("<input>", Path::new("."), source, 0)?;
+    assembler.finish()
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|error| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: error.to_string(),
+    })?;
+    let mut assembler = Assembler::new();
+    assembler.read(&path.display().to_string(), path.parent().unwrap_or(Path::new(".")), &source, 0)?;
+    assembler.finish()
+}
+
+// Where something came from, for error messages.
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    text: String,
+    at: Location,
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<Operand>, at: Location },
+    Bytes(Vec<Operand>),
+    Words(Vec<Operand>),
+}
+
+struct Assembler {
+    statements: Vec<(Address, Statement)>,
+    labels: HashMap<String, Address>,
+    constants: HashMap<String, Operand>,
+    address: Address,
+}
+
+impl Assembler {
+
+    fn new() -> Assembler {
+        Assembler {
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            address: ORIGIN,
+        }
+    }
+
+    // First pass: split the source into statements and give every label an address.
+    fn read(&mut self, file: &str, dir: &Path, source: &str, depth: usize) -> Result<(), AsmError> {
+        for (index, raw) in source.lines().enumerate() {
+            let at = |column: usize| Location { file: file.to_string(), line: index + 1, column: column + 1 };
+            let code = strip_comment(raw);
+
+            // Leading labels, as many as there are.
+            let mut rest = code;
+            let mut column = 0;
+            loop {
+                let trimmed = rest.trim_start();
+                column += rest.len() - trimmed.len();
+                rest = trimmed;
+                let word_len = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+                if word_len == 0 || !rest[word_len..].starts_with(':') {
+                    break;
+                }
+                let name = &rest[..word_len];
+                if self.labels.insert(name.to_string(), self.address).is_some() || self.constants.contains_key(name) {
+                    return Err(at(column).error(format!("`{}` is defined twice", name)));
+                }
+                rest = &rest[word_len + 1..];
+                column += word_len + 1;
+            }
+
+            let word_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if word_len == 0 {
+                continue;
+            }
+            let word = &rest[..word_len];
+            let operand_column = column + word_len;
+            let operands = split_operands(&rest[word_len..], operand_column, &at);
+
+            // NAME equ value / NAME = value
+            let after = rest[word_len..].trim_start();
+            let keyword_len = after.find(char::is_whitespace).unwrap_or(after.len());
+            let constant = if after[..keyword_len].eq_ignore_ascii_case("equ") {
+                Some(&after[keyword_len..])
+            } else {
+                after.strip_prefix('=')
+            };
+            if let Some(value) = constant {
+                if self.labels.contains_key(word) || self.constants.contains_key(word) {
+                    return Err(at(column).error(format!("`{}` is defined twice", word)));
+                }
+                let value_column = column + (rest.len() - value.len()) + (value.len() - value.trim_start().len());
+                self.constants.insert(word.to_string(), Operand { text: value.trim().to_string(), at: at(value_column) });
+                continue;
+            }
+
+            let size = match word.to_ascii_lowercase().as_str() {
+                "include" => {
+                    let name = match operands.as_slice() {
+                        [operand] => operand.text.trim_matches('"'),
+                        _ => return Err(at(column).error("include expects one file name")),
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(at(column).error("includes nest too deep, is there a cycle?"));
+                    }
+                    let path: PathBuf = dir.join(name);
+                    let source = std::fs::read_to_string(&path)
+                        .map_err(|error| at(column).error(format!("cannot include {}: {}", path.display(), error)))?;
+                    self.read(&path.display().to_string(), path.parent().unwrap_or(dir), &source, depth + 1)?;
+                    continue;
+                },
+                "db" => {
+                    let size = operands.len();
+                    self.statements.push((self.address, Statement::Bytes(operands)));
+                    size
+                },
+                "dw" => {
+                    let size = operands.len() * 2;
+                    self.statements.push((self.address, Statement::Words(operands)));
+                    size
+                },
+                _ => {
+                    // Only LD I, long takes two words.
+                    let long = operands.len() == 2 && operands[1].text.to_ascii_lowercase().starts_with("long ");
+                    self.statements.push((self.address, Statement::Instruction {
+                        mnemonic: word.to_ascii_uppercase(),
+                        operands,
+                        at: at(column),
+                    }));
+                    if long { 4 } else { 2 }
+                },
+            };
+            self.address = match self.address.checked_add(size as u16) {
+                Some(address) => address,
+                None => return Err(at(column).error("program does not fit in memory")),
+            };
+        }
+        Ok(())
+    }
+
+    // Second pass: everything has an address now, emit the bytes.
+    fn finish(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for (_, statement) in &self.statements {
+            match statement {
+                Statement::Bytes(operands) => {
+                    for operand in operands {
+                        rom.push(self.number(operand, 0xFF)? as u8);
+                    }
+                },
+                Statement::Words(operands) => {
+                    for operand in operands {
+                        rom.extend_from_slice(&(self.number(operand, 0xFFFF)? as u16).to_be_bytes());
+                    }
+                },
+                Statement::Instruction { mnemonic, operands, at } => {
+                    let instruction = self.instruction(mnemonic, operands, at)?;
+                    rom.extend_from_slice(&instruction.encode().0.to_be_bytes());
+                    if let Instruction::LoadILong(address) = instruction {
+                        rom.extend_from_slice(&address.to_be_bytes());
+                    }
+                },
+            }
+        }
+        Ok(rom)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], at: &Location) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+
+        let texts: Vec<String> = operands.iter().map(|operand| operand.text.to_ascii_uppercase()).collect();
+        let shape: Vec<&str> = texts.iter().map(|text| operand_kind(text)).collect();
+        let reg = |index: usize| register(&texts[index]).unwrap();
+        let addr = |index: usize| self.number(&operands[index], 0xFFF).map(|value| value as Address);
+        let byte = |index: usize| self.number(&operands[index], 0xFF).map(|value| value as u8);
+        let nibble = |index: usize| self.number(&operands[index], 0xF).map(|value| value as u8);
+
+        Ok(match (mnemonic, shape.as_slice()) {
+            ("CLS", []) => ClearDisplay,
+            ("RET", []) => Return,
+            ("SCD", [_]) => ScrollDown(nibble(0)?),
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => LowRes,
+            ("HIGH", []) => HighRes,
+            ("SCU", [_]) => ScrollUp(nibble(0)?),
+            ("SAVE", ["range"]) => { let (x, y) = range(&texts[0]).unwrap(); SaveRange(x, y) },
+            ("LOAD", ["range"]) => { let (x, y) = range(&texts[0]).unwrap(); LoadRange(x, y) },
+            ("PLANE", [_]) => SelectPlanes(nibble(0)?),
+            ("AUDIO", []) => LoadAudio,
+            ("PITCH", ["V"]) => SetPitch(reg(0)),
+            ("JP", [_]) => Jump(addr(0)?),
+            ("JP", ["V", _]) if reg(0) == 0 => JumpPlusZero(addr(1)?),
+            ("CALL", [_]) => Call(addr(0)?),
+            ("SE", ["V", "V"]) => SkipIfEqual(reg(0), reg(1)),
+            ("SE", ["V", _]) => SkipIfEqualsByte(reg(0), byte(1)?),
+            ("SNE", ["V", "V"]) => SkipIfNotEqual(reg(0), reg(1)),
+            ("SNE", ["V", _]) => SkipIfNotEqualsByte(reg(0), byte(1)?),
+            ("LD", ["V", "V"]) => Move(reg(0), reg(1)),
+            ("LD", ["V", "DT"]) => LoadDelayTimer(reg(0)),
+            ("LD", ["V", "K"]) => WaitForKeyPress(reg(0)),
+            ("LD", ["V", "[I]"]) => LoadRegisters(reg(0)),
+            ("LD", ["V", "R"]) => LoadFlags(reg(0)),
+            ("LD", ["V", _]) => LoadByte(reg(0), byte(1)?),
+            ("LD", ["I", "long"]) => {
+                let target = Operand { text: operands[1].text[5..].to_string(), at: operands[1].at.clone() };
+                LoadILong(self.number(&target, 0xFFFF)? as Address)
+            },
+            ("LD", ["I", _]) => LoadI(addr(1)?),
+            ("LD", ["DT", "V"]) => SetDelayTimer(reg(1)),
+            ("LD", ["ST", "V"]) => SetSoundTimer(reg(1)),
+            ("LD", ["F", "V"]) => LoadSprite(reg(1)),
+            ("LD", ["HF", "V"]) => LoadBigSprite(reg(1)),
+            ("LD", ["B", "V"]) => StoreBCD(reg(1)),
+            ("LD", ["[I]", "V"]) => StoreRegisters(reg(1)),
+            ("LD", ["R", "V"]) => StoreFlags(reg(1)),
+            ("ADD", ["V", "V"]) => Add(reg(0), reg(1)),
+            ("ADD", ["V", _]) => AddByte(reg(0), byte(1)?),
+            ("ADD", ["I", "V"]) => AddI(reg(1)),
+            ("OR", ["V", "V"]) => Or(reg(0), reg(1)),
+            ("AND", ["V", "V"]) => And(reg(0), reg(1)),
+            ("XOR", ["V", "V"]) => Xor(reg(0), reg(1)),
+            ("SUB", ["V", "V"]) => Sub(reg(0), reg(1)),
+            ("SUBN", ["V", "V"]) => ReverseSub(reg(0), reg(1)),
+            // Without Vy the shift works on Vx alone under every quirk setting.
+            ("SHR", ["V"]) => ShiftRight(reg(0), reg(0)),
+            ("SHR", ["V", "V"]) => ShiftRight(reg(0), reg(1)),
+            ("SHL", ["V"]) => ShiftLeft(reg(0), reg(0)),
+            ("SHL", ["V", "V"]) => ShiftLeft(reg(0), reg(1)),
+            ("RND", ["V", _]) => Random(reg(0), byte(1)?),
+            ("DRW", ["V", "V", _]) => Draw(reg(0), reg(1), nibble(2)?),
+            ("SKP", ["V"]) => SkipIfPressed(reg(0)),
+            ("SKNP", ["V"]) => SkipIfNotPressed(reg(0)),
+            ("NOOP", []) => NOOP,
+            _ if mnemonic_exists(mnemonic) => return Err(at.error(format!("invalid operands for {}", mnemonic))),
+            _ => return Err(at.error(format!("unknown instruction `{}`", mnemonic))),
+        })
+    }
+
+    // Evaluate an operand as a sum of numbers, labels and constants.
+    fn number(&self, operand: &Operand, max: i64) -> Result<i64, AsmError> {
+        let value = self.evaluate(operand, 0)?;
+        // Negative bytes are accepted as two's complement.
+        if value > max || value < -(max + 1) / 2 {
+            return Err(operand.at.error(format!("{} does not fit in {}", value, match max {
+                0xF => "a nibble",
+                0xFF => "a byte",
+                0xFFF => "12 bits",
+                _ => "16 bits",
+            })));
+        }
+        Ok(value & max)
+    }
+
+    fn evaluate(&self, operand: &Operand, depth: usize) -> Result<i64, AsmError> {
+        if depth > 32 {
+            return Err(operand.at.error("constant refers to itself"));
+        }
+        let text = operand.text.trim();
+        if text.is_empty() {
+            return Err(operand.at.error("missing value"));
+        }
+
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut expect_term = true;
+        let mut chars = text.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if (c == '+' || c == '-') && expect_term {
+                // Unary sign.
+                if c == '-' { sign = -sign; }
+                chars.next();
+            } else if c == '+' || c == '-' {
+                sign = if c == '-' { -1 } else { 1 };
+                expect_term = true;
+                chars.next();
+            } else if expect_term {
+                let mut end = text.len();
+                while let Some(&(index, c)) = chars.peek() {
+                    if !is_symbol_char(c) && c != '#' {
+                        end = index;
+                        break;
+                    }
+                    chars.next();
+                }
+                let term = &text[start..end];
+                let at = Location { column: operand.at.column + start, ..operand.at.clone() };
+                let value = self.term(term, &at, depth)?;
+                total = value.checked_mul(sign).and_then(|value| total.checked_add(value))
+                    .ok_or_else(|| at.error("value is too large"))?;
+                sign = 1;
+                expect_term = false;
+            } else {
+                let at = Location { column: operand.at.column + start, ..operand.at.clone() };
+                return Err(at.error(format!("unexpected `{}`", c)));
+            }
+        }
+        if expect_term {
+            return Err(operand.at.error("expression ends with an operator"));
+        }
+        Ok(total)
+    }
+
+    fn term(&self, term: &str, at: &Location, depth: usize) -> Result<i64, AsmError> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        if let Some(&address) = self.labels.get(term) {
+            return Ok(address as i64);
+        }
+        if let Some(constant) = self.constants.get(term) {
+            return self.evaluate(constant, depth + 1);
+        }
+        Err(at.error(format!("unknown symbol `{}`", term)))
+    }
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => {},
+        }
+    }
+    line
+}
+
+// Split on commas, remembering the column every operand starts at.
+fn split_operands(text: &str, column: usize, at: &dyn Fn(usize) -> Location) -> Vec<Operand> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut start = 0;
+    for piece in text.split(',') {
+        let leading = piece.len() - piece.trim_start().len();
+        operands.push(Operand { text: piece.trim().to_string(), at: at(column + start + leading) });
+        start += piece.len() + 1;
+    }
+    operands
+}
+
+fn register(text: &str) -> Option<Register> {
+    let digit = text.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+// Vx - Vy
+fn range(text: &str) -> Option<(Register, Register)> {
+    let (x, y) = text.split_once('-')?;
+    Some((register(x.trim())?, register(y.trim())?))
+}
+
+// What sort of operand this is, for picking the instruction form.
+// Anything that is not a keyword or register is a value.
+fn operand_kind(text: &str) -> &'static str {
+    if register(text).is_some() {
+        return "V";
+    }
+    if range(text).is_some() {
+        return "range";
+    }
+    if text.starts_with("LONG ") {
+        return "long";
+    }
+    match text {
+        "I" => "I",
+        "DT" => "DT",
+        "ST" => "ST",
+        "K" => "K",
+        "F" => "F",
+        "HF" => "HF",
+        "B" => "B",
+        "R" => "R",
+        "[I]" => "[I]",
+        _ => "value",
+    }
+}
+
+fn mnemonic_exists(mnemonic: &str) -> bool {
+    matches!(mnemonic,
+        "CLS" | "RET" | "SCD" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "SCU" | "SAVE" | "LOAD"
+        | "PLANE" | "AUDIO" | "PITCH" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+        | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" | "NOOP")
+}
+
+// Decimal, 0x/# hex or 0b binary. Everything that reads an address or a
+// count from the user goes through here so they all accept the same forms.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn disassembly_assembles_back_into_the_rom() {
+        // CALL a subroutine, draw a sprite in a loop, and the sprite's bytes after the code.
+        let rom = [
+            0x22, 0x0A, 0x60, 0x05, 0xA2, 0x10, 0xD0, 0x15, 0x12, 0x02,
+            0x00, 0xE0, 0x70, 0x01, 0x00, 0xEE,
+            0xF0, 0x90, 0xF0, 0x90, 0x90,
+        ];
+        assert_eq!(assemble(&disassemble(&rom)).unwrap(), rom);
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let source = "JP end\nCALL sub\nsub:\nRET\nend: JP end\n";
+        assert_eq!(assemble(source).unwrap(), [0x12, 0x06, 0x22, 0x04, 0x00, 0xEE, 0x12, 0x06]);
+    }
+
+    #[test]
+    fn constants() {
+        let source = "HEIGHT equ 5\nX = 3\nWIDE\tEQU\tHEIGHT + X\nDRW V0, V1, HEIGHT\nLD V2, WIDE\nLD V3, X - 1\n";
+        assert_eq!(assemble(source).unwrap(), [0xD0, 0x15, 0x62, 0x08, 0x63, 0x02]);
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let error = assemble("CLS\n  LD V0, missing\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 10));
+        assert_eq!(error.to_string(), "<input>:2:10: unknown symbol `missing`");
+
+        let error = assemble("start:\n\tFOO V1\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 2));
+        assert_eq!(error.message, "unknown instruction `FOO`");
+
+        let error = assemble("LD V0, 0x1FF").unwrap_err();
+        assert_eq!((error.line, error.column, error.message.as_str()), (1, 8, "511 does not fit in a byte"));
+
+        let error = assemble("CLS\ndb 1, 0x7FFFFFFFFFFFFFFF + 1").unwrap_err();
+        assert_eq!((error.line, error.column, error.message.as_str()), (2, 28, "value is too large"));
+        let error = assemble("dw -0x7FFFFFFFFFFFFFFF - 2").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 26));
+
+        let error = assemble("a: CLS\na: RET").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+    }
+}
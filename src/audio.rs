@@ -0,0 +1,70 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+use crate::emulator::Emulator;
+
+const SAMPLE_RATE: i32 = 44100;
+const VOLUME: f32 = 0.2;
+
+// Plays the XO-CHIP 128 bit pattern buffer in a loop. Plain CHIP-8 programs
+// get the default square wave the emulator starts with.
+struct PatternPlayer {
+    pattern: [u8; 16],
+    bits_per_sample: f32,
+    position: f32,
+    playing: bool,
+}
+
+impl AudioCallback for PatternPlayer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            if !self.playing {
+                *sample = 0.0;
+                continue;
+            }
+            let bit = self.position as usize;
+            let on = self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if on { VOLUME } else { -VOLUME };
+            self.position = (self.position + self.bits_per_sample) % 128.0;
+        }
+    }
+}
+
+pub struct Audio {
+    device: AudioDevice<PatternPlayer>,
+    freq: f32,
+}
+
+impl Audio {
+
+    pub fn new(sdl_context: &Sdl) -> Result<Audio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired, |spec| PatternPlayer {
+            pattern: [0; 16],
+            bits_per_sample: 4000.0 / spec.freq as f32,
+            position: 0.0,
+            playing: false,
+        })?;
+        device.resume();
+
+        let freq = device.spec().freq as f32;
+        Ok(Audio { device, freq })
+    }
+
+    // Copy the sound state over from the emulator, call this once per frame.
+    pub fn update(&mut self, emulator: &Emulator) {
+        let mut player = self.device.lock();
+        player.playing = emulator.sound_timer() > 0;
+        player.pattern = *emulator.audio_pattern();
+        player.bits_per_sample = emulator.playback_rate() / self.freq;
+    }
+}
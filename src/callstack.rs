@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::emulator::Emulator;
+use crate::instruction::{Address, Instruction};
+use crate::symbols::Symbols;
+
+// A CALL that has not returned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub caller: Address,        // The CALL instruction, RET goes to the one after it
+    pub callee: Address,        // Where the subroutine starts
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:03X} called 0x{:03X}", self.caller, self.callee)
+    }
+}
+
+// Rebuild frames for a stack that was changed behind the emulator's back
+// (save states, gdb writing SP). Frames that still match are kept, the rest
+// take their callee from the CALL instruction at the return address.
+pub(crate) fn sync(frames: &mut Vec<Frame>, stack: &[u16], memory: &[u8]) {
+    let kept = frames.iter().zip(stack).take_while(|(frame, &caller)| frame.caller == caller).count();
+    frames.truncate(kept);
+    for &caller in &stack[kept..] {
+        let callee = match Instruction::decode(memory, caller as usize) {
+            Some(Instruction::Call(address)) => address,
+            // Self modifying code, all we know is where it was called from.
+            _ => caller,
+        };
+        frames.push(Frame { caller, callee });
+    }
+}
+
+// Where each active subroutine is, innermost first: the address it is at
+// and where it starts, if known. The outermost one is the program itself.
+pub fn locations(emulator: &Emulator) -> Vec<(Address, Option<Address>)> {
+    let frames = emulator.frames();
+    let mut locations = vec![(emulator.pc(), frames.last().map(|frame| frame.callee))];
+    for (depth, frame) in frames.iter().enumerate().rev() {
+        let entry = if depth == 0 { None } else { Some(frames[depth - 1].callee) };
+        locations.push((frame.caller, entry));
+    }
+    locations
+}
+
+// `sub+4` from the symbols, `0x20A+4` from the frame without them.
+pub fn describe(symbols: &Symbols, address: Address, entry: Option<Address>) -> String {
+    name(symbols, address, entry).unwrap_or_else(|| format!("0x{:03X}", address))
+}
+
+// What describe shows, None when all there is to go on is the address.
+fn name(symbols: &Symbols, address: Address, entry: Option<Address>) -> Option<String> {
+    match (symbols.lookup(address), entry) {
+        (Some(_), _) => Some(symbols.describe(address)),
+        (None, Some(entry)) if entry <= address => Some(format!("0x{:03X}+{}", entry, address - entry)),
+        (None, _) => None,
+    }
+}
+
+// One line per frame, innermost first:
+//
+//     #0 0x20E in draw+4
+//     #1 0x204 in main+4
+//
+// Without symbols the outermost frame is only an address.
+pub fn backtrace(emulator: &Emulator, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    for (depth, (address, entry)) in locations(emulator).into_iter().enumerate() {
+        match name(symbols, address, entry) {
+            Some(name) => out.push_str(&format!("#{} 0x{:03X} in {}\n", depth, address, name)),
+            None => out.push_str(&format!("#{} 0x{:03X}\n", depth, address)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // CALL 0x204, then CALL 0x208, which spins
+    const ROM: [u8; 10] = [0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0x00, 0x12, 0x08];
+
+    #[test]
+    fn backtraces_name_what_they_can() {
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.load_rom(&ROM).unwrap();
+        emulator.run_frame(3).result.unwrap();
+
+        assert_eq!(backtrace(&emulator, &Symbols::new()), "#0 0x208 in 0x208+0\n#1 0x204 in 0x204+0\n#2 0x200\n");
+
+        let mut symbols = Symbols::new();
+        symbols.insert("main", 0x200);
+        symbols.insert("spin", 0x208);
+        assert_eq!(backtrace(&emulator, &symbols), "#0 0x208 in spin\n#1 0x204 in main+4\n#2 0x200 in main\n");
+    }
+}
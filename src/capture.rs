@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Screen;
+use crate::palette::Palette;
+
+// Pictures of the framebuffer. Only the top left width x height of the
+// screen is in use, every CHIP-8 pixel becomes scale x scale image pixels.
+
+// Palette indices, one byte per image pixel, row by row.
+fn pixels(screen: &Screen, width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(width * height * scale * scale);
+    for row in screen.iter().take(height) {
+        let line: Vec<u8> = row.iter().take(width).flat_map(|&pixel| std::iter::repeat_n(pixel & 0x3, scale)).collect();
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+    data
+}
+
+// RGB in the palette's colours.
+pub fn write_png<W: Write>(out: W, screen: &Screen, width: usize, height: usize, scale: usize, palette: &Palette) -> io::Result<()> {
+    let scale = scale.max(1);
+    let mut encoder = png::Encoder::new(out, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = pixels(screen, width, height, scale).into_iter().flat_map(|pixel| palette.color(pixel)).collect();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Binary PBM: lit pixels, on any plane, are black.
+pub fn write_pbm<W: Write>(mut out: W, screen: &Screen, width: usize, height: usize) -> io::Result<()> {
+    write!(out, "P4\n{} {}\n", width, height)?;
+    for row in screen.iter().take(height) {
+        let mut bytes = vec![0u8; width.div_ceil(8)];
+        for (x, &pixel) in row.iter().take(width).enumerate() {
+            if pixel != 0 {
+                bytes[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        out.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+// PNG or PBM, by the file's extension. PBM is always one pixel per pixel.
+pub fn save<P: AsRef<Path>>(path: P, screen: &Screen, width: usize, height: usize, scale: usize, palette: &Palette) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut out = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("pbm") => write_pbm(&mut out, screen, width, height)?,
+        _ => write_png(&mut out, screen, width, height, scale, palette)?,
+    }
+    out.flush()
+}
+
+// `<rom>.<n>.<extension>` with the first n that is not taken yet, next to
+// the ROM like save state slots.
+pub fn next_path(rom: &str, extension: &str) -> PathBuf {
+    (1..).map(|n| PathBuf::from(format!("{}.{}.{}", rom, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap_or_default()
+}
+
+/*
+Records presented frames into an animated GIF. The canvas has a fixed size,
+each frame is scaled to fill it, so switching between low and high
+resolution mid recording works when the canvas is a multiple of both, like
+the window. Frames in another palette than the one the recording started
+with bring their own colour table, so switching palettes works too.
+
+GIF delays are in hundredths of a second and 60Hz does not divide into them.
+Every frame gets the time between when it was and when the next one was
+shown rounded to centiseconds, and identical frames in a row are merged, so
+the delays add up to real time (2, 2, 1, 2, 2, 1, ...).
+*/
+pub struct Recorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    palette: Palette,               // The GIF's global colour table
+    width: usize,
+    height: usize,
+    frames: u64,                    // Frames seen, at 60Hz
+    pending: Option<(Vec<u8>, Palette, u64)>, // The last distinct frame, its palette and when it was first shown
+}
+
+impl Recorder {
+
+    pub fn create<P: AsRef<Path>>(path: P, width: usize, height: usize, palette: &Palette) -> io::Result<Recorder> {
+        let colors: Vec<u8> = palette.colors.iter().flatten().copied().collect();
+        let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width as u16, height as u16, &colors)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+        Ok(Recorder { encoder, palette: *palette, width, height, frames: 0, pending: None })
+    }
+
+    // Call once for every frame shown, in the palette it was shown in.
+    pub fn frame(&mut self, screen: &Screen, width: usize, height: usize, palette: &Palette) -> io::Result<()> {
+        let scale = (self.width / width).min(self.height / height).max(1);
+        let mut data = pixels(screen, width, height, scale);
+        // Pad a screen that does not fill the canvas, it only happens with odd canvas sizes.
+        if width * scale != self.width || height * scale != self.height {
+            data = pad(&data, width * scale, self.width, self.height);
+        }
+        match &self.pending {
+            Some((last, last_palette, _)) if *last == data && last_palette == palette => {},
+            _ => {
+                self.flush()?;
+                self.pending = Some((data, *palette, self.frames));
+            },
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some((data, palette, shown)) = self.pending.take() {
+            // Centiseconds since the recording started, rounded.
+            let at = |frame: u64| (frame * 100 + 30) / 60;
+            let frame = gif::Frame {
+                width: self.width as u16,
+                height: self.height as u16,
+                delay: (at(self.frames) - at(shown)).min(u16::MAX as u64) as u16,
+                buffer: data.into(),
+                palette: (palette != self.palette).then(|| palette.colors.iter().flatten().copied().collect()),
+                ..gif::Frame::default()
+            };
+            self.encoder.write_frame(&frame).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    // Write the last frame and close the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        self.encoder.into_inner().map_err(io::Error::other)?.flush()
+    }
+}
+
+fn pad(data: &[u8], data_width: usize, width: usize, height: usize) -> Vec<u8> {
+    let mut padded = vec![0; width * height];
+    for (row, line) in data.chunks(data_width).take(height).enumerate() {
+        let len = line.len().min(width);
+        padded[row * width..row * width + len].copy_from_slice(&line[..len]);
+    }
+    padded
+}
@@ -0,0 +1,540 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::callstack;
+use crate::debugger::{self, Debugger, Listed, Stop, STEPS_BETWEEN_POLLS};
+use crate::emulator::Emulator;
+use crate::font::FontSet;
+use crate::instruction::Address;
+use crate::quirks::Quirks;
+use crate::scheduler::{Scheduler, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use crate::symbols::Symbols;
+
+/*
+Debug Adapter Protocol server, for editors. Messages come in on `input` and
+go out on `output`, normally the adapter's stdin and stdout.
+
+A ROM has no source lines, so breakpoints are function breakpoints (a label
+from the symbol file or an address like 0x2A0) or instruction breakpoints.
+Launch arguments:
+
+    "program":     path to a .ch8 ROM or an .8o Octo source
+    "symbols":     optional symbol file, Octo sources bring their own labels
+    "quirks":      vip, chip48, schip or modern
+    "font":        vip, dream6800, eti660 or octo
+    "ipf":         instructions per frame
+    "stopOnEntry": stop before the first instruction
+*/
+
+// There is only the one thread.
+const THREAD_ID: u64 = 1;
+
+// variablesReference of the two scopes.
+const REGISTERS: u64 = 1;
+const STACK: u64 = 2;
+
+pub fn serve<R: Read + Send + 'static, W: Write>(input: R, output: W) -> io::Result<()> {
+    // Requests are read on their own thread so a running program can be paused.
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    Server { output, seq: 1, session: None, running: false, stop_on_entry: false }.run(requests)
+}
+
+// One `Content-Length: n` framed JSON message, None at the end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+struct Session {
+    emulator: Emulator,
+    debugger: Debugger,
+    symbols: Symbols,
+    function_breakpoints: Vec<Address>,
+    instruction_breakpoints: Vec<Address>,
+}
+
+impl Session {
+    // The debugger keeps one set, the protocol replaces each kind separately.
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        for &address in self.function_breakpoints.iter().chain(&self.instruction_breakpoints) {
+            self.debugger.add_breakpoint(address);
+        }
+    }
+}
+
+struct Server<W: Write> {
+    output: W,
+    seq: u64,
+    session: Option<Session>,
+    running: bool,
+    stop_on_entry: bool,
+}
+
+impl<W: Write> Server<W> {
+
+    fn run(&mut self, requests: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let request = if self.running {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+            match request {
+                Some(request) => {
+                    if !self.handle(&request)? {
+                        return Ok(());
+                    }
+                },
+                None => {
+                    let stop = match self.session.as_mut() {
+                        Some(session) => session.debugger.resume_for(&mut session.emulator, STEPS_BETWEEN_POLLS),
+                        None => None,
+                    };
+                    if let Some(stop) = stop {
+                        self.stopped(stop)?;
+                    }
+                },
+            }
+        }
+    }
+
+    // Returns false when the client is done with us.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+
+        let result = match command {
+            "initialize" => {
+                self.respond(request, Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsSteppingGranularity": true,
+                    "supportsEvaluateForHovers": true,
+                })))?;
+                self.event("initialized", json!({}))?;
+                return Ok(true);
+            },
+            "launch" => self.launch(arguments),
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(json!({})))?;
+                return Ok(false);
+            },
+            "configurationDone" => {
+                self.respond(request, Ok(json!({})))?;
+                if self.stop_on_entry {
+                    self.event("stopped", json!({ "reason": "entry", "threadId": THREAD_ID, "allThreadsStopped": true }))?;
+                } else {
+                    self.running = self.session.is_some();
+                }
+                return Ok(true);
+            },
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            _ => match self.session.as_mut() {
+                Some(session) => match command {
+                    "setBreakpoints" => {
+                        // Lines mean nothing to a ROM, say so for every one of them.
+                        let count = arguments["breakpoints"].as_array().map_or(0, Vec::len);
+                        let breakpoints: Vec<Value> = (0..count).map(|_| json!({
+                            "verified": false,
+                            "message": "ROMs have no source lines, use a function breakpoint on a label or address",
+                        })).collect();
+                        Ok(json!({ "breakpoints": breakpoints }))
+                    },
+                    "setFunctionBreakpoints" => {
+                        let mut breakpoints = Vec::new();
+                        session.function_breakpoints.clear();
+                        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+                            let name = breakpoint["name"].as_str().unwrap_or("");
+                            match session.symbols.resolve(name) {
+                                Some(address) => {
+                                    session.function_breakpoints.push(address);
+                                    breakpoints.push(json!({ "verified": true, "instructionReference": format!("0x{:03X}", address) }));
+                                },
+                                None => breakpoints.push(json!({ "verified": false, "message": format!("no label `{}`", name) })),
+                            }
+                        }
+                        session.update_breakpoints();
+                        Ok(json!({ "breakpoints": breakpoints }))
+                    },
+                    "setInstructionBreakpoints" => {
+                        let mut breakpoints = Vec::new();
+                        session.instruction_breakpoints.clear();
+                        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+                            let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+                            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                            let address = session.symbols.resolve(reference).map(|address| {
+                                (address as i64).checked_add(offset).and_then(|address| Address::try_from(address).ok())
+                            });
+                            match address {
+                                Some(Some(address)) => {
+                                    session.instruction_breakpoints.push(address);
+                                    breakpoints.push(json!({ "verified": true, "instructionReference": format!("0x{:03X}", address) }));
+                                },
+                                Some(None) => breakpoints.push(json!({ "verified": false, "message": "address is outside memory" })),
+                                None => breakpoints.push(json!({ "verified": false })),
+                            }
+                        }
+                        session.update_breakpoints();
+                        Ok(json!({ "breakpoints": breakpoints }))
+                    },
+                    "stackTrace" => Ok(stack_trace(session)),
+                    "scopes" => Ok(json!({ "scopes": [
+                        { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                        { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                    ]})),
+                    "variables" => Ok(variables(session, arguments["variablesReference"].as_u64().unwrap_or(0))),
+                    "evaluate" => evaluate(session, arguments["expression"].as_str().unwrap_or("")),
+                    "readMemory" => read_memory(session, arguments),
+                    "disassemble" => disassemble(session, arguments),
+                    "continue" => {
+                        self.running = true;
+                        Ok(json!({ "allThreadsContinued": true }))
+                    },
+                    "pause" => {
+                        self.respond(request, Ok(json!({})))?;
+                        self.running = false;
+                        self.event("stopped", json!({ "reason": "pause", "threadId": THREAD_ID, "allThreadsStopped": true }))?;
+                        return Ok(true);
+                    },
+                    "next" | "stepIn" | "stepOut" => {
+                        let stop = match command {
+                            "next" => session.debugger.step_over(&mut session.emulator),
+                            "stepIn" => session.debugger.step(&mut session.emulator),
+                            _ => session.debugger.step_out(&mut session.emulator),
+                        };
+                        self.respond(request, Ok(json!({})))?;
+                        self.stopped(stop)?;
+                        return Ok(true);
+                    },
+                    _ => Err(format!("`{}` is not supported", command)),
+                },
+                None => Err("no program has been launched".to_string()),
+            },
+        };
+        self.respond(request, result)?;
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"].as_str().ok_or("launch needs a `program`")?;
+        let quirks = match arguments["quirks"].as_str() {
+            Some(name) => Quirks::from_name(name).ok_or(format!("unknown quirks profile `{}`", name))?,
+            None => Quirks::default(),
+        };
+        let font = match arguments["font"].as_str() {
+            Some(name) => FontSet::from_name(name).ok_or(format!("unknown font `{}`", name))?,
+            None => FontSet::default(),
+        };
+        let instructions_per_frame = arguments["ipf"].as_u64().map_or(DEFAULT_INSTRUCTIONS_PER_FRAME, |ipf| ipf as u32);
+
+        let mut symbols = match arguments["symbols"].as_str() {
+            Some(path) => Symbols::load(path).map_err(|error| format!("{}: {}", path, error))?,
+            None => Symbols::new(),
+        };
+        let rom = if program.ends_with(".8o") {
+            let compiled = crate::octo::compile_file(program).map_err(|error| error.to_string())?;
+            for (name, &address) in compiled.labels.iter() {
+                symbols.insert(name, address);
+            }
+            compiled.rom
+        } else {
+            std::fs::read(program).map_err(|error| format!("{}: {}", program, error))?
+        };
+
+        // Set up the same way as the window frontend.
+        let mut emulator = Emulator::new(quirks);
+        emulator.load_font(font);
+        emulator.setup_noop();
+        emulator.load_rom(&rom).map_err(|error| error.to_string())?;
+
+        self.session = Some(Session {
+            emulator,
+            debugger: Debugger::new(Scheduler::new(instructions_per_frame)),
+            symbols,
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+        });
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(json!({}))
+    }
+
+    // Tell the client why the program is no longer running.
+    fn stopped(&mut self, stop: Stop) -> io::Result<()> {
+        self.running = false;
+        let (reason, text) = match &stop {
+            Stop::Done => ("step", None),
+            Stop::Breakpoint(_) => ("breakpoint", None),
+            Stop::Watchpoint(_) => ("data breakpoint", Some(stop.to_string())),
+            Stop::Error(_) => ("exception", Some(stop.to_string())),
+            Stop::WaitingForKey | Stop::Stuck(_) => ("pause", Some(stop.to_string())),
+            Stop::Exited => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                return self.event("terminated", json!({}));
+            },
+        };
+        self.event("stopped", json!({
+            "reason": reason,
+            "description": text,
+            "text": text,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }))
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut message = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => message["body"] = body,
+            Err(error) => message["message"] = json!(error),
+        }
+        self.send(message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+// pc first, then every CALL that has not returned, innermost first.
+fn stack_trace(session: &Session) -> Value {
+    let frames: Vec<Value> = callstack::locations(&session.emulator).into_iter().enumerate()
+        .map(|(id, (address, entry))| json!({
+            "id": id,
+            "name": callstack::describe(&session.symbols, address, entry),
+            "instructionPointerReference": format!("0x{:03X}", address),
+            "line": 0,
+            "column": 0,
+        }))
+        .collect();
+    json!({ "stackFrames": frames, "totalFrames": frames.len() })
+}
+
+fn variables(session: &Session, reference: u64) -> Value {
+    let emulator = &session.emulator;
+    let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+    let address = |name: &str, value: u16| json!({
+        "name": name,
+        "value": format!("0x{:04X} {}", value, session.symbols.describe(value)),
+        "memoryReference": format!("0x{:04X}", value),
+        "variablesReference": 0,
+    });
+
+    let variables: Vec<Value> = match reference {
+        REGISTERS => {
+            let mut variables: Vec<Value> = emulator.v.iter().enumerate()
+                .map(|(register, value)| variable(format!("V{:X}", register), format!("0x{:02X} ({})", value, value)))
+                .collect();
+            variables.push(address("I", emulator.i));
+            variables.push(address("PC", emulator.pc));
+            variables.push(variable("SP".to_string(), emulator.sp.to_string()));
+            variables.push(variable("DT".to_string(), emulator.delay_timer.to_string()));
+            variables.push(variable("ST".to_string(), emulator.sound_timer.to_string()));
+            variables
+        },
+        STACK => emulator.stack[..emulator.sp as usize].iter().enumerate()
+            .map(|(slot, &return_address)| address(&format!("[{}]", slot), return_address))
+            .collect(),
+        _ => Vec::new(),
+    };
+    json!({ "variables": variables })
+}
+
+// Registers by name, labels, and `[addr]` for a byte of memory.
+fn evaluate(session: &Session, expression: &str) -> Result<Value, String> {
+    let emulator = &session.emulator;
+    let expression = expression.trim();
+    let upper = expression.to_ascii_uppercase();
+    let value = match upper.as_str() {
+        "I" => emulator.i as usize,
+        "PC" => emulator.pc as usize,
+        "SP" => emulator.sp as usize,
+        "DT" => emulator.delay_timer as usize,
+        "ST" => emulator.sound_timer as usize,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            let register = usize::from_str_radix(&upper[1..], 16).map_err(|_| format!("no register `{}`", expression))?;
+            emulator.v[register] as usize
+        },
+        _ if expression.starts_with('[') && expression.ends_with(']') => {
+            let address = session.symbols.resolve(expression[1..expression.len() - 1].trim())
+                .ok_or(format!("not an address: {}", expression))?;
+            emulator.memory[address as usize] as usize
+        },
+        _ => session.symbols.resolve(expression).ok_or(format!("unknown `{}`", expression))? as usize,
+    };
+    Ok(json!({ "result": format!("0x{:X} ({})", value, value), "variablesReference": 0 }))
+}
+
+fn read_memory(session: &Session, arguments: &Value) -> Result<Value, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or("");
+    let start = session.symbols.resolve(reference).ok_or(format!("not an address: {}", reference))? as i64
+        + arguments["offset"].as_i64().unwrap_or(0);
+    let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+    let memory = &session.emulator.memory;
+    let start = start.clamp(0, memory.len() as i64) as usize;
+    let end = start.saturating_add(count).min(memory.len());
+    Ok(json!({
+        "address": format!("0x{:04X}", start),
+        "data": base64(&memory[start..end]),
+        "unreadableBytes": count - (end - start),
+    }))
+}
+
+fn disassemble(session: &Session, arguments: &Value) -> Result<Value, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or("");
+    let base = session.symbols.resolve(reference).ok_or(format!("not an address: {}", reference))? as i64
+        + arguments["offset"].as_i64().unwrap_or(0);
+    let offset = arguments["instructionOffset"].as_i64().unwrap_or(0);
+    let memory = &session.emulator.memory;
+    let count = (arguments["instructionCount"].as_u64().unwrap_or(0) as usize).min(memory.len());
+    let invalid = |address: i64| json!({ "address": format!("0x{:04X}", address.max(0)), "instruction": "", "presentationHint": "invalid" });
+
+    // The client wants exactly `count` instructions, whatever could not be
+    // listed before the start or past the end of memory is marked invalid.
+    let mut instructions = Vec::new();
+    if (0..memory.len() as i64).contains(&base) {
+        let unreachable = offset.saturating_neg().saturating_sub(base / 2).clamp(0, count as i64) as usize;
+        instructions.extend((0..unreachable).map(|_| invalid(0)));
+        for Listed { address, bytes, text } in debugger::listing(memory, base as Address, offset, count - unreachable) {
+            let mut instruction = json!({ "address": format!("0x{:04X}", address), "instructionBytes": bytes, "instruction": text });
+            if let Some((name, 0)) = session.symbols.lookup(address) {
+                instruction["symbol"] = json!(name);
+            }
+            instructions.push(instruction);
+        }
+    }
+    while instructions.len() < count {
+        instructions.push(invalid(memory.len() as i64));
+    }
+    Ok(json!({ "instructions": instructions }))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (index, &byte)| word | (byte as u32) << (16 - index * 8));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                out.push(ALPHABET[(word >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server<Vec<u8>> {
+        let mut symbols = Symbols::new();
+        symbols.insert("main", 0x2A0);
+        let session = Session {
+            emulator: Emulator::new(Quirks::default()),
+            debugger: Debugger::new(Scheduler::new(DEFAULT_INSTRUCTIONS_PER_FRAME)),
+            symbols,
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+        };
+        Server { output: Vec::new(), seq: 1, session: Some(session), running: false, stop_on_entry: false }
+    }
+
+    // Hand the server one request and read back everything it sent.
+    fn request(server: &mut Server<Vec<u8>>, command: &str, arguments: Value) -> Vec<Value> {
+        server.output.clear();
+        let request = json!({ "seq": 7, "type": "request", "command": command, "arguments": arguments });
+        assert!(server.handle(&request).unwrap());
+        let mut output = &server.output[..];
+        std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+    }
+
+    #[test]
+    fn instruction_breakpoints_outside_memory_are_not_verified() {
+        let mut server = server();
+        let messages = request(&mut server, "setInstructionBreakpoints", json!({ "breakpoints": [
+            { "instructionReference": "0x200", "offset": 4 },
+            { "instructionReference": "main" },
+            { "instructionReference": "0xFFFE", "offset": 2 },
+            { "instructionReference": "0x200", "offset": -0x202 },
+            { "instructionReference": "0x200", "offset": i64::MAX },
+            { "instructionReference": "nowhere" },
+        ]}));
+
+        assert_eq!(messages.len(), 1);
+        let response = &messages[0];
+        assert_eq!(response["type"], "response");
+        assert_eq!(response["request_seq"], 7);
+        assert_eq!(response["success"], true);
+        let verified: Vec<&Value> = response["body"]["breakpoints"].as_array().unwrap().iter().map(|breakpoint| &breakpoint["verified"]).collect();
+        assert_eq!(verified, [true, true, false, false, false, false]);
+        assert_eq!(response["body"]["breakpoints"][0]["instructionReference"], "0x204");
+        assert_eq!(response["body"]["breakpoints"][2]["message"], "address is outside memory");
+
+        let debugger = &server.session.as_ref().unwrap().debugger;
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x204, 0x2A0]);
+    }
+
+    #[test]
+    fn requests_before_launch_fail() {
+        let mut server = server();
+        server.session = None;
+        let messages = request(&mut server, "stackTrace", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[0]["message"], "no program has been launched");
+
+        let messages = request(&mut server, "threads", json!({}));
+        assert_eq!(messages[0]["body"]["threads"][0]["id"], THREAD_ID);
+    }
+}
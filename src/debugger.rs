@@ -0,0 +1,430 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use crate::asm::parse_number;
+use crate::callstack;
+use crate::emulator::Emulator;
+use crate::error::{EmuError, StepOutcome};
+use crate::instruction::{Address, Instruction};
+use crate::scheduler::Scheduler;
+use crate::symbols::Symbols;
+use crate::watch::{Watch, WatchHit};
+
+// Why execution stopped and control went back to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    // The requested step, step over or step out is done.
+    Done,
+    Breakpoint(Address),
+    // The last instruction wrote to watched locations.
+    Watchpoint(Vec<WatchHit>),
+    Exited,
+    // FX0A with no key held, press one and continue.
+    WaitingForKey,
+    // An unconditional jump to itself, nothing will ever change.
+    Stuck(Address),
+    Error(EmuError),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Done => Ok(()),
+            Stop::Breakpoint(address) => write!(f, "breakpoint at 0x{:03X}", address),
+            Stop::Watchpoint(hits) => {
+                let hits: Vec<String> = hits.iter().map(|hit| format!("watchpoint {}", hit)).collect();
+                write!(f, "{}", hits.join("\n"))
+            },
+            Stop::Exited => write!(f, "program exited"),
+            Stop::WaitingForKey => write!(f, "waiting for a key press"),
+            Stop::Stuck(address) => write!(f, "stuck in a jump to itself at 0x{:03X}", address),
+            Stop::Error(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// Runs an emulator under control: breakpoints and stepping. Timers still
+// tick once per frame of the scheduler's instruction budget, so the program
+// sees the same speed as in the window frontend.
+pub struct Debugger {
+    breakpoints: BTreeSet<Address>,
+    scheduler: Scheduler,
+    frame_budget: u32,                  // Instructions the current frame gets
+    frame_steps: u32,                   // Instructions run since the timers last ticked
+    symbols: Symbols,                   // Names for breakpoints and backtraces
+}
+
+impl Debugger {
+
+    pub fn new(mut scheduler: Scheduler) -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            frame_budget: scheduler.next_budget(),
+            scheduler,
+            frame_steps: 0,
+            symbols: Symbols::new(),
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn add_breakpoint(&mut self, address: Address) {
+        self.breakpoints.insert(address);
+    }
+
+    // Returns false if there was no breakpoint there.
+    pub fn remove_breakpoint(&mut self, address: Address) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = Address> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Run exactly one instruction.
+    pub fn step(&mut self, emulator: &mut Emulator) -> Stop {
+        loop {
+            let outcome = emulator.step();
+            if outcome != Ok(StepOutcome::WaitingForVBlank) {
+                self.end_of_step(emulator);
+            }
+            return match outcome {
+                Ok(StepOutcome::Executed) => Stop::Done,
+                Ok(StepOutcome::WaitingForKey) => Stop::WaitingForKey,
+                Ok(StepOutcome::Exited) => Stop::Exited,
+                Ok(StepOutcome::Watchpoint) => Stop::Watchpoint(emulator.take_watch_hits()),
+                // Nothing to wait for here, start the next frame and draw.
+                Ok(StepOutcome::WaitingForVBlank) => {
+                    self.end_frame(emulator);
+                    continue;
+                },
+                Err(error) => Stop::Error(error),
+            };
+        }
+    }
+
+    // Run until a breakpoint or the program stops by itself.
+    pub fn resume(&mut self, emulator: &mut Emulator) -> Stop {
+        self.run_until(emulator, None, |_| false).unwrap_or(Stop::Done)
+    }
+
+    // Like resume, but give up after `steps` instructions and return None.
+    // For frontends that need to look at their input while the program runs.
+    pub fn resume_for(&mut self, emulator: &mut Emulator, steps: u32) -> Option<Stop> {
+        self.run_until(emulator, Some(steps), |_| false)
+    }
+
+    // Like step, but a CALL runs until it returns.
+    pub fn step_over(&mut self, emulator: &mut Emulator) -> Stop {
+        match emulator.read_instruction() {
+            Some(Instruction::Call(_)) => {
+                let (return_to, depth) = (emulator.pc.wrapping_add(2), emulator.sp);
+                self.run_until(emulator, None, |emulator| emulator.pc == return_to && emulator.sp == depth)
+                    .unwrap_or(Stop::Done)
+            },
+            _ => self.step(emulator),
+        }
+    }
+
+    // Run until the current subroutine returns.
+    pub fn step_out(&mut self, emulator: &mut Emulator) -> Stop {
+        if emulator.sp == 0 {
+            return self.resume(emulator);
+        }
+        let depth = emulator.sp;
+        self.run_until(emulator, None, |emulator| emulator.sp < depth).unwrap_or(Stop::Done)
+    }
+
+    // Breakpoints are checked after every step, so the instruction we
+    // stopped on last time runs even if it has one.
+    fn run_until(&mut self, emulator: &mut Emulator, limit: Option<u32>, done: impl Fn(&Emulator) -> bool) -> Option<Stop> {
+        let mut steps = 0;
+        while limit.is_none_or(|limit| steps < limit) {
+            if emulator.read_instruction() == Some(Instruction::Jump(emulator.pc)) {
+                return Some(Stop::Stuck(emulator.pc));
+            }
+            match self.step(emulator) {
+                Stop::Done if done(emulator) => return Some(Stop::Done),
+                Stop::Done if self.breakpoints.contains(&emulator.pc) => return Some(Stop::Breakpoint(emulator.pc)),
+                Stop::Done => {},
+                stop => return Some(stop),
+            }
+            steps += 1;
+        }
+        None
+    }
+
+    fn end_of_step(&mut self, emulator: &mut Emulator) {
+        self.frame_steps += 1;
+        if self.frame_steps >= self.frame_budget {
+            self.end_frame(emulator);
+        }
+    }
+
+    // Frames without a single instruction, at speeds under 60Hz, only tick the timers.
+    fn end_frame(&mut self, emulator: &mut Emulator) {
+        loop {
+            emulator.timer_ticks();
+            self.frame_budget = self.scheduler.next_budget();
+            if self.frame_budget > 0 {
+                break;
+            }
+        }
+        self.frame_steps = 0;
+    }
+}
+
+// V0-VF, I, pc, sp, timers and the stack.
+pub fn registers(emulator: &Emulator) -> String {
+    let mut out = String::new();
+    for (index, values) in emulator.v.chunks(8).enumerate() {
+        let line: Vec<String> = values.iter().enumerate()
+            .map(|(register, value)| format!("V{:X} {:02X}", index * 8 + register, value))
+            .collect();
+        let _ = writeln!(out, "{}", line.join("  "));
+    }
+    let _ = writeln!(out, "I  {:04X}  PC {:04X}  SP {:X}  DT {:02X}  ST {:02X}",
+        emulator.i, emulator.pc, emulator.sp, emulator.delay_timer, emulator.sound_timer);
+    let stack: Vec<String> = emulator.stack[..emulator.sp as usize].iter().rev()
+        .map(|address| format!("{:04X}", address))
+        .collect();
+    let _ = writeln!(out, "stack: {}", if stack.is_empty() { "empty".to_string() } else { stack.join(" ") });
+    out
+}
+
+// 16 bytes a row starting at `address`.
+pub fn hexdump(emulator: &Emulator, address: usize, len: usize) -> String {
+    let mut out = String::new();
+    let end = (address + len).min(emulator.memory.len());
+    for row in (address..end).step_by(16) {
+        let bytes: Vec<String> = emulator.memory[row..(row + 16).min(end)].iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let _ = writeln!(out, "{:04X}  {}", row, bytes.join(" "));
+    }
+    out
+}
+
+// How many instructions resume_for should run between checks for a pause
+// request from a remote frontend, short enough to feel immediate.
+pub const STEPS_BETWEEN_POLLS: u32 = 1000;
+
+// One instruction of a disassembly listing.
+pub(crate) struct Listed {
+    pub(crate) address: Address,
+    pub(crate) bytes: String,           // In hex, as they are in memory
+    pub(crate) text: String,
+}
+
+// `count` instructions starting `offset` instructions from `address`,
+// negative offsets going back. Stops early at the end of memory.
+pub(crate) fn listing(memory: &[u8], address: Address, offset: i64, count: usize) -> Vec<Listed> {
+    // Instructions can't be found walking backwards, assume two bytes each.
+    let back = offset.min(0).unsigned_abs().saturating_mul(2).min(address as u64) as u16;
+    let skip = offset.max(0) as usize;
+    let mut at = address - back;
+    let mut listed = Vec::new();
+    for index in 0..skip.saturating_add(count) {
+        let (text, size) = match Instruction::decode(memory, at as usize) {
+            Some(instruction) => (instruction.to_string(), instruction.size()),
+            None => ("???".to_string(), 2),
+        };
+        if index >= skip {
+            let bytes = (0..size)
+                .filter_map(|offset| memory.get(at.wrapping_add(offset) as usize))
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            listed.push(Listed { address: at, bytes, text });
+        }
+        at = match at.checked_add(size) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    listed
+}
+
+// Disassembly from `before` instructions ahead of `address` to `after`
+// instructions past it. pc is marked with `>`, breakpoints with `*`.
+pub fn disassembly(emulator: &Emulator, debugger: &Debugger, address: Address, before: u16, after: u16) -> String {
+    let mut out = String::new();
+    let count = before as usize + after as usize + 1;
+    for Listed { address: at, bytes, text } in listing(&emulator.memory, address, -(before as i64), count) {
+        let marker = match (at == emulator.pc, debugger.breakpoints.contains(&at)) {
+            (true, true) => ">*",
+            (true, false) => "> ",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+        if let Some((label, 0)) = debugger.symbols.lookup(at) {
+            let _ = writeln!(out, "{}:", label);
+        }
+        let _ = writeln!(out, "{} {:04X}  {:<8}  {}", marker, at, bytes, text);
+    }
+    out
+}
+
+const HELP: &str = "\
+commands:
+  s, step [n]          run n instructions (default 1)
+  n, next              step over a CALL
+  f, finish            run until the current subroutine returns
+  c, continue          run until a breakpoint
+  b, break [addr]      set a breakpoint, list them without an address
+  d, delete addr       remove a breakpoint
+  w, watch [what]      stop after writes to Vx, I, addr or addr-addr, list without
+  unwatch what         remove a watchpoint
+  r, regs              registers and stack
+  bt, backtrace        the subroutines that have not returned yet
+  m, mem [addr] [len]  memory, around I by default
+  l, list [addr]       disassembly, around pc by default
+  screen               the framebuffer
+  press k, release k   hold or let go of keypad key k (0-F)
+  q, quit
+b, d and l take labels from --symbols as well as addresses
+an empty line repeats the last command";
+
+// Interactive debugger on `input`/`output`, until quit or end of input.
+pub fn repl(emulator: &mut Emulator, debugger: &mut Debugger, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    writeln!(output, "type `help` for commands")?;
+    write!(output, "{}", disassembly(emulator, debugger, emulator.pc, 0, 0))?;
+    write!(output, "(chip8) ")?;
+    output.flush()?;
+
+    let mut last = String::new();
+    for line in input.lines() {
+        let line = line?;
+        let line = if line.trim().is_empty() { last.clone() } else { line };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = |index: usize| words.get(index).map(|word| parse_number(word).and_then(|number| usize::try_from(number).ok()));
+
+        let stop = match words.as_slice() {
+            [] => None,
+            ["q" | "quit", ..] => return Ok(()),
+            ["h" | "help", ..] => {
+                writeln!(output, "{}", HELP)?;
+                None
+            },
+            ["s" | "step", ..] => match argument(1) {
+                Some(None) => {
+                    writeln!(output, "not a count: {}", words[1])?;
+                    None
+                },
+                count => {
+                    let mut stop = Stop::Done;
+                    for _ in 0..count.flatten().unwrap_or(1) {
+                        stop = debugger.step(emulator);
+                        if stop != Stop::Done {
+                            break;
+                        }
+                    }
+                    Some(stop)
+                },
+            },
+            ["n" | "next", ..] => Some(debugger.step_over(emulator)),
+            ["f" | "finish", ..] => Some(debugger.step_out(emulator)),
+            ["c" | "continue", ..] => Some(debugger.resume(emulator)),
+            ["b" | "break"] => {
+                for address in debugger.breakpoints() {
+                    writeln!(output, "0x{:03X}", address)?;
+                }
+                None
+            },
+            ["b" | "break", _, ..] => {
+                match debugger.symbols.resolve(words[1]) {
+                    Some(address) => debugger.add_breakpoint(address),
+                    None => writeln!(output, "not an address: {}", words[1])?,
+                }
+                None
+            },
+            ["d" | "delete", _, ..] => {
+                match debugger.symbols.resolve(words[1]) {
+                    Some(address) if debugger.remove_breakpoint(address) => {},
+                    _ => writeln!(output, "no breakpoint at {}", words[1])?,
+                }
+                None
+            },
+            ["w" | "watch"] => {
+                for watch in emulator.watches() {
+                    writeln!(output, "{}", watch)?;
+                }
+                None
+            },
+            [command @ ("w" | "watch" | "unwatch"), what, ..] => {
+                match Watch::from_name(what) {
+                    Some(watch) if *command != "unwatch" => emulator.add_watch(watch),
+                    Some(watch) if emulator.remove_watch(watch) => {},
+                    Some(_) => writeln!(output, "not watching {}", what)?,
+                    None => writeln!(output, "can't watch `{}`, expected Vx, I, addr or addr-addr", what)?,
+                }
+                None
+            },
+            ["r" | "regs", ..] => {
+                write!(output, "{}", registers(emulator))?;
+                None
+            },
+            ["bt" | "backtrace", ..] => {
+                write!(output, "{}", callstack::backtrace(emulator, &debugger.symbols))?;
+                None
+            },
+            ["m" | "mem", ..] => {
+                let address = match argument(1) {
+                    Some(Some(address)) => address,
+                    _ => (emulator.i as usize & !0xF).saturating_sub(0x10),
+                };
+                let len = argument(2).flatten().unwrap_or(0x40);
+                write!(output, "{}", hexdump(emulator, address, len))?;
+                None
+            },
+            ["l" | "list", ..] => {
+                let address = words.get(1).and_then(|word| debugger.symbols.resolve(word)).unwrap_or(emulator.pc);
+                write!(output, "{}", disassembly(emulator, debugger, address, 5, 10))?;
+                None
+            },
+            ["screen", ..] => {
+                for row in emulator.display().iter().take(emulator.height()) {
+                    let line: String = row.iter().take(emulator.width()).map(|&pixel| if pixel != 0 { '#' } else { '.' }).collect();
+                    writeln!(output, "{}", line)?;
+                }
+                None
+            },
+            [action @ ("press" | "release"), key, ..] => {
+                match u8::from_str_radix(key, 16) {
+                    Ok(key) if key < 16 => if *action == "press" { emulator.key_down(key) } else { emulator.key_up(key) },
+                    _ => writeln!(output, "not a key: {}", key)?,
+                }
+                None
+            },
+            _ => {
+                writeln!(output, "unknown command `{}`, try `help`", line.trim())?;
+                None
+            },
+        };
+
+        if let Some(stop) = stop {
+            if stop != Stop::Done {
+                writeln!(output, "{}", stop)?;
+            }
+            if let Stop::Error(_) = stop {
+                write!(output, "{}", callstack::backtrace(emulator, &debugger.symbols))?;
+            }
+            write!(output, "{}", disassembly(emulator, debugger, emulator.pc, 0, 0))?;
+        }
+        last = line;
+        write!(output, "(chip8) ")?;
+        output.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::emulator::MEMORY_SIZE;
+use crate::instruction::{Address, Instruction};
+
+// Programs are loaded here, every address in a ROM is relative to it.
+pub const ORIGIN: u16 = 0x200;
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_asm(f, &|address| format!("0x{:03X}", address))
+    }
+}
+
+impl Instruction {
+
+    // Write the mnemonic, naming address operands with `address`.
+    pub(crate) fn write_asm(&self, f: &mut dyn Write, address: &dyn Fn(Address) -> String) -> fmt::Result {
+        match *self {
+            Instruction::ClearDisplay => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::LoadILong(addr) => write!(f, "LD I, long {}", address(addr)),
+            Instruction::SelectPlanes(n) => write!(f, "PLANE {}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::SetPitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::Jump(addr) => write!(f, "JP {}", address(addr)),
+            Instruction::Call(addr) => write!(f, "CALL {}", address(addr)),
+            Instruction::SkipIfEqualsByte(x, byte) => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipIfNotEqualsByte(x, byte) => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipIfEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, byte) => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::AddByte(x, byte) => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            Instruction::Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::ReverseSub(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(addr) => write!(f, "LD I, {}", address(addr)),
+            Instruction::JumpPlusZero(addr) => write!(f, "JP V0, {}", address(addr)),
+            Instruction::Random(x, byte) => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfPressed(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelayTimer(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKeyPress(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelayTimer(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSoundTimer(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadSprite(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::StoreBCD(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegisters(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LoadBigSprite(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::NOOP => write!(f, "NOOP"),
+        }
+    }
+}
+
+// What an address is used as, in order of which name wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Subroutine,
+    Code,
+    Data,
+}
+
+// Finds the code in a ROM by following every path from the entry point,
+// the same way the CPU would, so sprites and other data between routines
+// come out as bytes instead of nonsense instructions.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    code: BTreeMap<usize, Instruction>,     // Offset into the ROM -> instruction found there
+    labels: BTreeMap<Address, LabelKind>,
+}
+
+impl<'a> Disassembler<'a> {
+
+    // Only what fits in memory above 0x200 is disassembled, the emulator
+    // would not load the rest anyway.
+    pub fn new(rom: &'a [u8]) -> Disassembler<'a> {
+        let mut disassembler = Disassembler {
+            rom: &rom[..rom.len().min(MEMORY_SIZE - ORIGIN as usize)],
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembler.trace(ORIGIN);
+        disassembler
+    }
+
+    fn offset(&self, address: Address) -> Option<usize> {
+        let offset = address.checked_sub(ORIGIN)? as usize;
+        if offset < self.rom.len() { Some(offset) } else { None }
+    }
+
+    fn label(&mut self, address: Address, kind: LabelKind) {
+        let entry = self.labels.entry(address).or_insert(kind);
+        *entry = (*entry).min(kind);
+    }
+
+    fn trace(&mut self, entry: Address) {
+        let mut pending = vec![entry];
+        while let Some(mut address) = pending.pop() {
+            while let Some(offset) = self.offset(address) {
+                if self.code.contains_key(&offset) {
+                    break;
+                }
+                let instruction = match Instruction::decode(self.rom, offset) {
+                    Some(instruction) => instruction,
+                    None => break,
+                };
+                self.code.insert(offset, instruction);
+                let next = address.wrapping_add(instruction.size());
+
+                match instruction {
+                    Instruction::Jump(target) => {
+                        self.label(target, LabelKind::Code);
+                        pending.push(target);
+                        break;
+                    },
+                    Instruction::Call(target) => {
+                        self.label(target, LabelKind::Subroutine);
+                        pending.push(target);
+                    },
+                    // The target depends on V0 at run time, nothing to follow.
+                    Instruction::JumpPlusZero(_) | Instruction::Return | Instruction::Exit => break,
+                    Instruction::SkipIfEqualsByte(..)
+                    | Instruction::SkipIfNotEqualsByte(..)
+                    | Instruction::SkipIfEqual(..)
+                    | Instruction::SkipIfNotEqual(..)
+                    | Instruction::SkipIfPressed(_)
+                    | Instruction::SkipIfNotPressed(_) => {
+                        // Skips step over F000 NNNN as a whole.
+                        let skipped = match self.offset(next).and_then(|offset| Instruction::decode(self.rom, offset)) {
+                            Some(instruction) => instruction.size(),
+                            None => 2,
+                        };
+                        pending.push(next.wrapping_add(skipped));
+                    },
+                    Instruction::LoadI(target) | Instruction::LoadILong(target) => {
+                        self.label(target, LabelKind::Data);
+                    },
+                    _ => {},
+                }
+                address = next;
+            }
+        }
+    }
+
+    // Assembler style listing: labels, one instruction per line, data as db.
+    pub fn listing(&self) -> String {
+        // Only keep labels that start a line, one in the middle of an
+        // instruction would never be printed.
+        let starts: Vec<Address> = self.line_starts();
+        let names: BTreeMap<Address, String> = self.labels.iter()
+            .filter(|(address, _)| starts.binary_search(address).is_ok())
+            .map(|(&address, kind)| {
+                let prefix = match kind {
+                    LabelKind::Subroutine => "sub",
+                    LabelKind::Code => "loc",
+                    LabelKind::Data => "data",
+                };
+                (address, format!("{}_{:03X}", prefix, address))
+            })
+            .collect();
+        let address_name = |address: Address| {
+            names.get(&address).cloned().unwrap_or_else(|| format!("0x{:03X}", address))
+        };
+
+        let mut out = String::new();
+        for (index, &start) in starts.iter().enumerate() {
+            if let Some(name) = names.get(&start) {
+                let _ = writeln!(out, "{}:", name);
+            }
+            let offset = (start - ORIGIN) as usize;
+            let mut line = String::from("    ");
+            match self.code.get(&offset) {
+                Some(instruction) => {
+                    let _ = instruction.write_asm(&mut line, &address_name);
+                    let bytes = &self.rom[offset..offset + instruction.size() as usize];
+                    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    let _ = writeln!(out, "{:<27} ; {:03X}  {}", line, start, hex);
+                },
+                None => {
+                    let end = starts.get(index + 1).map_or(self.rom.len(), |&next| (next - ORIGIN) as usize);
+                    let bytes: Vec<String> = self.rom[offset..end].iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    let _ = write!(line, "db {}", bytes.join(", "));
+                    let _ = writeln!(out, "{:<27} ; {:03X}", line, start);
+                },
+            }
+        }
+        out
+    }
+
+    // Addresses where a listing line starts: every instruction, and data
+    // split at labels and into rows of at most 8 bytes.
+    fn line_starts(&self) -> Vec<Address> {
+        let mut starts = Vec::new();
+        let mut offset = 0;
+        let mut data_row = 0;
+        while offset < self.rom.len() {
+            let address = ORIGIN + offset as u16;
+            match self.code.get(&offset) {
+                Some(instruction) => {
+                    starts.push(address);
+                    offset += instruction.size() as usize;
+                    data_row = 0;
+                },
+                None => {
+                    if data_row == 0 || data_row == 8 || self.labels.contains_key(&address) {
+                        starts.push(address);
+                        data_row = 0;
+                    }
+                    data_row += 1;
+                    offset += 1;
+                },
+            }
+        }
+        starts
+    }
+}
+
+// Disassemble a ROM loaded at 0x200.
+pub fn disassemble(rom: &[u8]) -> String {
+    Disassembler::new(rom).listing()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_and_data_are_told_apart() {
+        // Draw a sprite in a loop, the sprite's ten bytes after the code.
+        let rom = [0xA2, 0x06, 0xD0, 0x15, 0x12, 0x02, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x10, 0x20, 0x30, 0x40, 0x50];
+        assert_eq!(disassemble(&rom), "    LD I, data_206          ; 200  A206
+loc_202:
+    DRW V0, V1, 5           ; 202  D015
+    JP loc_202              ; 204  1202
+data_206:
+    db 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x10, 0x20, 0x30 ; 206
+    db 0x40, 0x50           ; 20E
+");
+    }
+
+    #[test]
+    fn jumps_into_the_middle_of_an_instruction() {
+        // CALL 0x205 lands on the second byte of LD V0, 0x12 and runs 1200 from there.
+        let rom = [0x22, 0x05, 0x12, 0x04, 0x60, 0x12, 0x00, 0xE0, 0x12, 0x08];
+        let disassembler = Disassembler::new(&rom);
+        assert_eq!(disassembler.code.get(&5), Some(&Instruction::Jump(0x200)));
+        assert_eq!(disassembler.line_starts(), [0x200, 0x202, 0x204, 0x206, 0x208]);
+
+        // The listing keeps the outer instruction and can't name the address.
+        let listing = disassembler.listing();
+        assert!(listing.contains("CALL 0x205 "));
+        assert!(listing.contains("LD V0, 0x12 "));
+        assert!(!listing.contains("sub_205"));
+    }
+
+    #[test]
+    fn skips_follow_both_ways() {
+        // SE V0, 1 either runs or skips the F000 NNNN after it.
+        let rom = [0x30, 0x01, 0xF0, 0x00, 0x02, 0x0A, 0x00, 0xE0, 0x12, 0x08, 0x00, 0xFD];
+        let disassembler = Disassembler::new(&rom);
+        assert_eq!(disassembler.code.get(&2), Some(&Instruction::LoadILong(0x020A)));
+        assert_eq!(disassembler.code.get(&6), Some(&Instruction::ClearDisplay));
+        assert!(disassembler.listing().contains("LD I, long data_20A"));
+    }
+
+    #[test]
+    fn roms_past_the_end_of_memory_are_cut_off() {
+        let rom = vec![0x12; MEMORY_SIZE];
+        let disassembler = Disassembler::new(&rom);
+        assert_eq!(disassembler.rom.len(), MEMORY_SIZE - ORIGIN as usize);
+        assert_eq!(disassembler.line_starts().last(), Some(&0xFFFC));
+        disassembler.listing();
+    }
+}
@@ -1,26 +1,30 @@
-
 use sdl2::Sdl;
+use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
+use sdl2::video::FullscreenType;
 
+use crate::emulator::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
 
-const SCREEN_WIDTH: usize = 64;
-const SCREEN_HEIGHT: usize = 32;
-
+// The letterbox around the screen when the window is not 2:1.
 const SDL_BLANK_COLOR: Color = Color::RGB(0x0, 0x0, 0x0);
-const SDL_BLOCK_COLOR: Color = Color::RGB(0x34, 0xE6, 0x2F);
 
 
 pub struct Display {
-    pub canvas: WindowCanvas
+    pub canvas: WindowCanvas,
+    palette: Palette,
+    phosphor: Option<Phosphor>,     // Fades pixels out instead of blinking them
 }
 
 impl Display {
 
-    // Create a new display
-    pub fn new() -> (Self, Sdl) {
-        
+    // Create a new display, a resizable window of 64x32 pixels at `scale`x
+    pub fn new(scale: usize, palette: Palette) -> (Self, Sdl) {
+        let scale = scale.max(1);
+
         // Create a new SDL2 context
         let sdl_context = sdl2::init().unwrap();
 
@@ -31,10 +35,11 @@ impl Display {
         let window = video_subsystem
             .window(
                 "CHIP-8 Emulator",
-                (SCREEN_WIDTH * 10).try_into().unwrap(),
-                (SCREEN_HEIGHT * 10).try_into().unwrap(),
+                (SCREEN_WIDTH * scale).try_into().unwrap(),
+                (SCREEN_HEIGHT * scale).try_into().unwrap(),
             )
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
 
@@ -47,24 +52,73 @@ impl Display {
         
 
         // Return the new screen
-        (Display { canvas }, sdl_context)
+        (Display { canvas, palette, phosphor: None }, sdl_context)
+    }
+
+    // The largest whole number of window pixels per CHIP-8 pixel that fits
+    // a width x height screen in the window, at least 1.
+    pub fn scale(&self, width: usize, height: usize) -> usize {
+        let (window_width, window_height) = self.canvas.output_size().unwrap_or((0, 0));
+        (window_width as usize / width).min(window_height as usize / height).max(1)
+    }
+
+    // The drawn area in window pixels, without the letterbox.
+    pub fn size(&self) -> (usize, usize) {
+        let scale = self.scale(SCREEN_WIDTH, SCREEN_HEIGHT);
+        (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale)
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    // Turn persistence on, or off with None.
+    pub fn set_phosphor(&mut self, phosphor: Option<Phosphor>) {
+        self.phosphor = phosphor;
+    }
+
+    pub fn phosphor_mut(&mut self) -> Option<&mut Phosphor> {
+        self.phosphor.as_mut()
+    }
+
+    // Borderless fullscreen at the desktop's resolution, the screen is letterboxed in it.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.window_mut().set_fullscreen(mode)
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
     }
 
-    pub fn draw_screen(&mut self, screen: &[[bool; SCREEN_WIDTH]; SCREEN_HEIGHT]) {
-        for y in 0..SCREEN_HEIGHT {
-            for x in 0..SCREEN_WIDTH {
-                if screen[y][x] {
-                    self.canvas.set_draw_color(SDL_BLOCK_COLOR);
-                } else {
-                    self.canvas.set_draw_color(SDL_BLANK_COLOR);
-                }
-                self.canvas
-                    .fill_rect(Rect::new(x as i32 * 10, y as i32 * 10, 10, 10))
-                    .unwrap();
-            }
-
-            self.canvas.present();
+    // Draw the top left width x height of the framebuffer, as big as it
+    // fits and centred in the window.
+    pub fn draw_screen(&mut self, screen: &Screen, width: usize, height: usize) {
+        let scale = self.scale(width, height);
+        let (window_width, window_height) = self.canvas.output_size().unwrap_or((0, 0));
+        let left = (window_width as i32 - (width * scale) as i32).max(0) / 2;
+        let top = (window_height as i32 - (height * scale) as i32).max(0) / 2;
+
+        let colors = match self.phosphor.as_mut() {
+            Some(phosphor) => phosphor.frame(screen, width, height, &self.palette),
+            None => screen.iter().take(height).flat_map(|row| row.iter().take(width).map(|&pixel| self.palette.color(pixel))).collect(),
+        };
+
+        self.canvas.set_draw_color(SDL_BLANK_COLOR);
+        self.canvas.clear();
+        for (index, [r, g, b]) in colors.into_iter().enumerate() {
+            let (x, y) = (index % width, index / width);
+            self.canvas.set_draw_color(Color::RGB(r, g, b));
+            self.canvas
+                .fill_rect(Rect::new(left + (x * scale) as i32, top + (y * scale) as i32, scale as u32, scale as u32))
+                .unwrap();
         }
+
+        self.canvas.present();
     }
 
     pub fn clear(&mut self) {
@@ -73,4 +127,43 @@ impl Display {
         // Clear the canvas
         self.canvas.clear();
     }
-}
\ No newline at end of file
+}
+
+// Map the keyboard to the hex keypad.
+pub fn map_keys(key: Keycode) -> Option<u8> {
+    match key {
+		Keycode::Num1 => Some(0x1),
+		Keycode::Num2 => Some(0x2),
+		Keycode::Num3 => Some(0x3),
+		Keycode::Num4 => Some(0xC),
+		Keycode::Q => Some(0x4),
+		Keycode::W => Some(0x5),
+		Keycode::E => Some(0x6),
+		Keycode::R => Some(0xD),
+		Keycode::A => Some(0x7),
+		Keycode::S => Some(0x8),
+		Keycode::D => Some(0x9),
+		Keycode::F => Some(0xE),
+		Keycode::Z => Some(0xA),
+		Keycode::X => Some(0x0),
+		Keycode::C => Some(0xB),
+		Keycode::V => Some(0xF),
+		_ => None,
+	}
+}
+
+// Save state hotkeys: F1-F4 save to slot 1-4, F5-F8 load from slot 1-4.
+// Returns the slot and whether it is a save.
+pub fn save_slot(key: Keycode) -> Option<(u8, bool)> {
+    match key {
+        Keycode::F1 => Some((1, true)),
+        Keycode::F2 => Some((2, true)),
+        Keycode::F3 => Some((3, true)),
+        Keycode::F4 => Some((4, true)),
+        Keycode::F5 => Some((1, false)),
+        Keycode::F6 => Some((2, false)),
+        Keycode::F7 => Some((3, false)),
+        Keycode::F8 => Some((4, false)),
+        _ => None,
+    }
+}
@@ -0,0 +1,1195 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+
+use crate::callstack::{self, Frame};
+use crate::error::{EmuError, StepOutcome};
+use crate::font::{FontSet, BIG_CHARACTERS, BIG_FONT_ADDRESS, FONT_ADDRESS};
+use crate::instruction::Instruction;
+use crate::profile::Profiler;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::{RandomSource, SeededRandom};
+use crate::trace::{Registers, Tracer};
+use crate::watch::{Location, Watch, WatchHit};
+
+
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+
+// SUPER-CHIP high resolution mode. The framebuffer is always this big,
+// low resolution mode only uses the top left 64x32 of it.
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// Every pixel holds one bit per XO-CHIP plane, bit 0 is plane 1.
+pub type Screen = [[u8; HIRES_WIDTH]; HIRES_HEIGHT];
+
+// XO-CHIP extends memory to 64K, plain CHIP-8 programs simply never reach past 0xFFF.
+pub const MEMORY_SIZE: usize = 0x10000;
+
+// How a frame went: how its last instruction ended, and how many
+// instructions ran before it stopped, the failing one included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRun {
+    pub result: Result<StepOutcome, EmuError>,
+    pub instructions: u32,
+}
+
+
+
+    /*
+    Memory: 4KiB, 64KiB for XO-CHIP
+    Registers: V0 to VF ....
+    Stack: 16 slots
+    framebuffer: 64x32, 128x64 in SUPER-CHIP high resolution mode, two XO-CHIP planes
+
+    */
+
+    /*
+    Memory Layout:
+    |- 0x000 - 0x1FF: Chip 8 interpreter (contains font set in emulator)
+    |- 0x050 - 0x0A0: Used for the built in 4x5 pixel font set (0-F)
+    |- 0x0A0 - 0x140: SUPER-CHIP 8x10 font set (0-F)
+    |- 0x200 - 0xFFF: Program ROM and work RAM
+    |- 0x1000 - 0xFFFF: XO-CHIP program ROM and work RAM
+    */
+
+
+
+pub struct Emulator {
+    pub memory: Vec<u8>,         // 64K memory; 0x0000 - 0xFFFF
+    pub(crate) v: [u8; 16],             // 16 8-bit registers; 0x0 - 0xF
+    pub(crate) i: u16,                         // Memory address register
+    pub(crate) pc: u16,                        // Program counter
+    pub(crate) stack: [u16; 16],       // Stack; 16 levels of 16-bit values
+    pub(crate) sp: u8,                         // Stack pointer; points to the top of the stack
+    pub(crate) frames: Vec<Frame>,             // One per return address on the stack, with the subroutine it called
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) display: Screen,
+    pub(crate) hires: bool,                    // SUPER-CHIP 128x64 mode
+    pub(crate) keys: [bool; 16],
+    pub(crate) draw_flag: bool,
+    pub(crate) rpl: [u8; 16],                  // SUPER-CHIP RPL user flags; FX75/FX85
+    pub(crate) planes: u8,                     // XO-CHIP planes selected by FN01
+    pub(crate) audio_pattern: [u8; 16],        // XO-CHIP 1-bit sample buffer loaded by F002
+    pub(crate) pitch: u8,                      // XO-CHIP playback rate set by FX3A
+    pub(crate) exited: bool,                   // Set by 00FD
+    pub(crate) vblank: bool,                   // Set by the 60Hz tick, consumed by a draw when display_wait is on
+    pub(crate) quirks: Quirks,
+    pub(crate) rom_hash: u64,                  // Save states only load on top of the same ROM
+    pub(crate) rng: Box<dyn RandomSource>,     // Feeds CXNN
+    watches: Vec<Watch>,
+    watch_hits: Vec<WatchHit>,                 // Writes the last instruction made to watched locations
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
+}
+
+impl Emulator {
+
+    pub fn new(quirks: Quirks) -> Emulator {
+        let mut emulator = Emulator {
+            memory: vec![0x0; MEMORY_SIZE],
+            v: [0; 16],
+            i: 0x200,
+            pc: 0x200,
+            stack: [0; 16],
+            sp: 0,
+            frames: Vec::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            display: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires: false,
+            keys: [false; 16],
+            draw_flag: false,
+            rpl: [0; 16],
+            planes: 1,
+            // A plain 500Hz square wave until a program loads its own pattern.
+            audio_pattern: [0xF0; 16],
+            pitch: 64,
+            exited: false,
+            vblank: true,
+            quirks,
+            rom_hash: rom_hash(&[]),
+            rng: Box::new(SeededRandom::from_entropy()),
+            watches: Vec::new(),
+            watch_hits: Vec::new(),
+            tracer: None,
+            profiler: None,
+        };
+
+        emulator.load_font(FontSet::default());
+
+        let big_font = BIG_FONT_ADDRESS as usize;
+        for (digit, sprite) in BIG_CHARACTERS.iter().enumerate() {
+            emulator.memory[big_font + digit * 10..big_font + digit * 10 + 10].copy_from_slice(sprite);
+        }
+
+        emulator
+    }
+
+    // Write a font set into the interpreter area for FX29.
+    pub fn load_font(&mut self, font: FontSet) {
+        let start = FONT_ADDRESS as usize;
+        for (digit, sprite) in font.characters().iter().enumerate() {
+            self.memory[start + digit * 5..start + digit * 5 + 5].copy_from_slice(sprite);
+        }
+    }
+
+
+    // Replace the source CXNN draws from.
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.rng = source;
+    }
+
+    // Make CXNN reproducible.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Box::new(SeededRandom::new(seed));
+    }
+
+
+    // This is my touch to chip8
+    // The interpreter area is left alone, the fonts live there.
+    pub fn setup_noop(&mut self){
+        for pos in (0x200..self.memory.len()).step_by(2){
+            self.memory[pos] = 0xF0;
+            self.memory[pos + 1] = 0x69;
+        }
+    }
+
+    // Log every step to a trace, or stop tracing with None.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // Count where instructions go, or stop profiling with None.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Stop with StepOutcome::Watchpoint after any instruction that writes here.
+    pub fn add_watch(&mut self, watch: Watch) {
+        if !self.watches.contains(&watch) {
+            self.watches.push(watch);
+        }
+    }
+
+    // Returns false if it was not being watched.
+    pub fn remove_watch(&mut self, watch: Watch) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|&watched| watched != watch);
+        self.watches.len() != before
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    // The writes that made the last step return StepOutcome::Watchpoint.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    pub fn key_down(&mut self, key: u8) {
+        self.keys[key as usize] = true;
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.keys[key as usize] = false;
+    }
+    // Call this at 60Hz, independent of how many instructions ran.
+    pub fn timer_ticks(&mut self) {
+        self.vblank = true;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+
+        // Decrement delay timer if it's greater than zero every tick
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    pub fn read_rom<P: std::convert::AsRef<std::path::Path>>(mut self, path: P) -> io::Result<Emulator> {
+        let mut rom = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut rom)?;
+        self.load_rom(&rom)?;
+        Ok(self)
+    }
+
+    // Copy a program into memory at 0x200.
+    pub fn load_rom(&mut self, rom: &[u8]) -> io::Result<()> {
+        match self.memory.get_mut(0x200..0x200 + rom.len()) {
+            Some(program) => program.copy_from_slice(rom),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "ROM does not fit in memory")),
+        }
+        self.rom_hash = rom_hash(rom);
+        Ok(())
+    }
+
+    // Hash of the loaded program, save states carry it.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // Read only views for the frontends.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // The subroutines that have not returned yet, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // Call after changing stack or sp directly.
+    pub(crate) fn sync_frames(&mut self) {
+        let depth = (self.sp as usize).min(self.stack.len());
+        callstack::sync(&mut self.frames, &self.stack[..depth], &self.memory);
+    }
+
+    // The whole framebuffer, only the top left width() x height() of it is in use.
+    pub fn display(&self) -> &Screen {
+        &self.display
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { SCREEN_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { SCREEN_HEIGHT }
+    }
+
+    // The frontend plays the audio pattern while this is above zero.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    // Playback rate of the audio pattern in bits per second.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // True once the program ran 00FD.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn draw_flag(&self) -> bool {
+        self.draw_flag
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn read_instruction(&self) -> Option<Instruction> {
+        Instruction::decode(&self.memory, self.pc as usize)
+    }
+
+    // Fetch, decode and run the instruction at pc.
+    pub fn step(&mut self) -> Result<StepOutcome, EmuError> {
+        let instruction = self.read_instruction();
+        let mut profiler = self.profiler.take();
+        if let Some(profiler) = profiler.as_mut() {
+            profiler.begin(self.pc, &self.frames);
+        }
+        let result = match self.tracer.take() {
+            Some(mut tracer) if tracer.wants(self.pc) => {
+                let pc = self.pc as usize;
+                let size = instruction.map_or(2, |instruction| instruction.size() as usize);
+                let opcode = self.memory.get(pc..pc + size).unwrap_or(&[]).to_vec();
+                let before = Registers::of(self);
+                let result = self.run_instruction(instruction);
+                tracer.record(pc as u16, &opcode, instruction, &before, &Registers::of(self), &result);
+                self.tracer = Some(tracer);
+                result
+            },
+            tracer => {
+                self.tracer = tracer;
+                self.run_instruction(instruction)
+            },
+        };
+        if let Some(profiler) = profiler.as_mut() {
+            profiler.finish(instruction, &result);
+        }
+        self.profiler = profiler;
+        result
+    }
+
+    // Run one 60Hz frame: up to `instructions` steps, then the timers tick once.
+    pub fn run_frame(&mut self, instructions: u32) -> FrameRun {
+        let mut run = FrameRun { result: Ok(StepOutcome::Executed), instructions: 0 };
+        for _ in 0..instructions {
+            run.result = self.step();
+            match run.result {
+                // A draw held for the next frame did not run.
+                Ok(StepOutcome::WaitingForVBlank) => break,
+                Ok(StepOutcome::Exited | StepOutcome::Watchpoint) | Err(_) => {
+                    run.instructions += 1;
+                    return run;
+                },
+                Ok(_) => run.instructions += 1,
+            }
+        }
+        self.timer_ticks();
+        run
+    }
+
+    pub fn run_instruction(&mut self, instruction: Option<Instruction>) -> Result<StepOutcome, EmuError> {
+        self.draw_flag = false;
+        self.watch_hits.clear();
+        let mut outcome = StepOutcome::Executed;
+
+        self.pc = match instruction {
+            Some(Instruction::ClearDisplay) => {self.clear_display(); self.pc.wrapping_add(2)}, //clear display
+            Some(Instruction::Return) => {
+                // Set the program counter to return position
+                if self.sp == 0 {
+                    return Err(EmuError::StackUnderflow { pc: self.pc });
+                }
+                self.sp -= 1;
+                self.frames.pop();
+                self.stack[self.sp as usize].wrapping_add(2)
+            },
+            Some(Instruction::ScrollDown(rows)) => {
+                self.scroll(0, rows as isize);
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::ScrollUp(rows)) => {
+                self.scroll(0, -(rows as isize));
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::ScrollRight) => {
+                self.scroll(4, 0);
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::ScrollLeft) => {
+                self.scroll(-4, 0);
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Exit) => {
+                // Stay on the exit instruction, the frontend decides what to do.
+                self.exited = true;
+                outcome = StepOutcome::Exited;
+                self.pc
+            },
+            Some(Instruction::LowRes) => {
+                self.hires = false;
+                self.display = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+                self.draw_flag = true;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::HighRes) => {
+                self.hires = true;
+                self.display = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+                self.draw_flag = true;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Jump(address)) => address,
+            Some(Instruction::Call(address)) => {
+                // go to an adress but to return.
+                if self.sp as usize == self.stack.len() {
+                    return Err(EmuError::StackOverflow { pc: self.pc });
+                }
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+                self.frames.push(Frame { caller: self.pc, callee: address });
+                address
+            },
+            Some(Instruction::SkipIfEqualsByte(register, value)) => {
+                if self.v[register] == value {
+                    self.skip()
+                } else {
+                    self.pc.wrapping_add(2)
+                }
+            },
+            Some(Instruction::SkipIfNotEqualsByte(register, value)) => {
+                if self.v[register] != value {
+                    self.skip()
+                } else {
+                    self.pc.wrapping_add(2)
+                }
+            },
+            Some(Instruction::SaveRange(regx, regy)) => {
+                // Works in either direction and leaves I alone.
+                for (offset, register) in Self::register_range(regx, regy).enumerate() {
+                    self.write_byte(self.i as usize + offset, self.v[register])?;
+                }
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::LoadRange(regx, regy)) => {
+                for (offset, register) in Self::register_range(regx, regy).enumerate() {
+                    let value = self.read_byte(self.i as usize + offset)?;
+                    self.set_v(register, value)?;
+                }
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::SkipIfEqual(regx, regy)) => {
+                if self.v[regx] == self.v[regy] {
+                    self.skip()
+                } else {
+                    self.pc.wrapping_add(2)
+                }
+            },
+            Some(Instruction::LoadByte(register, value)) => {
+                self.set_v(register, value)?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::AddByte(register, value)) => {
+                self.set_v(register, self.v[register].wrapping_add(value))?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Move(regx, regy)) => {
+                self.set_v(regx, self.v[regy])?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Or(regx, regy)) => {
+                self.set_v(regx, self.v[regx] | self.v[regy])?;
+                if self.quirks.logic_resets_vf { self.set_v(0x0F, 0)? }
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::And(regx, regy))=> {
+                self.set_v(regx, self.v[regx] & self.v[regy])?;
+                if self.quirks.logic_resets_vf { self.set_v(0x0F, 0)? }
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Xor(regx, regy)) => {
+                self.set_v(regx, self.v[regx] ^ self.v[regy])?;
+                if self.quirks.logic_resets_vf { self.set_v(0x0F, 0)? }
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Add(regx, regy)) => {
+                // VF is written last so it wins when it is also the target.
+                let (result, carry) = self.v[regx].overflowing_add(self.v[regy]);
+                self.set_v(regx, result)?;
+                self.set_v(0x0F, carry as u8)?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::Sub(regx, regy)) => {
+                // VF is 1 when there was no borrow.
+                let (result, borrow) = self.v[regx].overflowing_sub(self.v[regy]);
+                self.set_v(regx, result)?;
+                self.set_v(0x0F, !borrow as u8)?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::ShiftRight(regx, regy)) => {
+                let value = if self.quirks.shift_uses_vy { self.v[regy] } else { self.v[regx] };
+                self.set_v(regx, value >> 1)?;
+                self.set_v(0x0F, value & 0x1)?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::ReverseSub(regx, regy)) => {
+                let (result, borrow) = self.v[regy].overflowing_sub(self.v[regx]);
+                self.set_v(regx, result)?;
+                self.set_v(0x0F, !borrow as u8)?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::ShiftLeft(regx, regy)) => {
+                let value = if self.quirks.shift_uses_vy { self.v[regy] } else { self.v[regx] };
+                self.set_v(regx, value << 1)?;
+                self.set_v(0x0F, value >> 7)?; // Most significant bit.
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::SkipIfNotEqual(regx, regy)) => {
+                if self.v[regx] != self.v[regy] {
+                    self.skip()
+                } else {
+                    self.pc.wrapping_add(2)
+                }
+            },
+            Some(Instruction::LoadI(address)) => {
+                self.set_i(address)?;
+                self.pc.wrapping_add(2)
+            },
+            Some(Instruction::LoadILong(address)) => {
+                self.set_i(address)?;
+                self.pc.wrapping_add(4)
+            },
+            Some(Instruction::JumpPlusZero(addr)) => {
+                // BXNN on CHIP-48 and SUPER-CHIP
+                let register = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+                addr + (self.v[register] as u16)
+            },
+
+            Some(Instruction::Random(x, val)) => {
+                let value = val & self.rng.next_byte();
+                self.set_v(x, value)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::Draw(_, _, _)) if self.quirks.display_wait && !self.vblank => {
+                // Hold the draw until the next frame starts.
+                outcome = StepOutcome::WaitingForVBlank;
+                self.pc
+            },
+
+            Some(Instruction::Draw(regx, regy, value)) => {
+                let (width, height) = (self.width(), self.height());
+                // The starting position always wraps, only the sprite itself can be clipped.
+                let coordx = self.v[regx] as usize % width;
+                let coordy = self.v[regy] as usize % height;
+
+                // DXY0 draws a 16x16 SUPER-CHIP sprite, two bytes per row.
+                let (rows, cols) = if value == 0 { (16, 16) } else { (value as usize, 8) };
+                let bytes_per_row = cols / 8;
+
+                let mut collision = false;
+                self.draw_flag = true;
+                self.vblank = false;
+
+                // With both XO-CHIP planes selected the sprite for plane 2
+                // follows the one for plane 1 in memory.
+                let mut start = self.i as usize;
+                for plane in [1u8, 2] {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
+
+                    // Display the rows and collumns of char
+                    for row in 0..rows {
+                        let offset = start + row * bytes_per_row;
+                        if self.quirks.clip_sprites && coordy + row >= height {
+                            continue;
+                        }
+                        let pixels = self.read_bytes(offset, bytes_per_row)?
+                            .iter()
+                            .fold(0u16, |acc, &byte| acc << 8 | byte as u16);
+                        for col in 0..cols {
+                            if self.quirks.clip_sprites && coordx + col >= width {
+                                break;
+                            }
+                            if (pixels & (1 << (cols - 1 - col))) != 0 {
+                                let x = (coordx + col) % width;
+                                let y = (coordy + row) % height;
+                                // to wrap around
+                                collision |= self.display[y][x] & plane != 0;
+                                self.display[y][x] ^= plane;
+                            }
+                        }
+                    }
+                    start += rows * bytes_per_row;
+                }
+
+                self.set_v(0xF, collision as u8)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::SkipIfPressed(x)) => {
+                let key = self.v[x] & 0x0F;
+                if self.keys[key as usize] {
+                    self.skip()
+                }else {
+                   self.pc.wrapping_add(2)
+                }
+            },
+
+            Some(Instruction::SkipIfNotPressed(x)) => {
+                let key = self.v[x] & 0x0F;
+                if !self.keys[key as usize] {
+                    self.skip()
+                } else{
+                    self.pc.wrapping_add(2)
+                }
+            },
+
+            Some(Instruction::LoadDelayTimer(register)) => {
+                self.set_v(register, self.delay_timer)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::WaitForKeyPress(register)) => {
+                let mut pressed = false;
+                for (i, &key) in self.keys.iter().enumerate() {
+                    if key {
+                        pressed = true;
+                        self.set_v(register, i as u8)?;
+                        break;
+                    }
+                }
+                if pressed {
+                    self.pc.wrapping_add(2)
+                }else {
+                    outcome = StepOutcome::WaitingForKey;
+                    self.pc
+                }
+            },
+
+            Some(Instruction::SetDelayTimer(register)) => {
+                self.delay_timer = self.v[register];
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::SetSoundTimer(register)) => {
+                self.sound_timer = self.v[register];
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::AddI(register)) => {
+                self.set_i(self.i.wrapping_add(self.v[register] as u16))?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::LoadSprite(register)) => {
+                self.set_i(FONT_ADDRESS + (self.v[register] & 0x0F) as u16 * 5)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::StoreBCD(register)) => {
+                self.write_byte(self.i as usize, self.v[register] / 100)?; // hundreds
+                self.write_byte(self.i as usize + 1, (self.v[register] / 10) % 10)?; // tens
+                self.write_byte(self.i as usize + 2, self.v[register] % 10)?; // ones
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::StoreRegisters(register)) => {
+                for i in 0..=register {
+                    self.write_byte(self.i as usize + i, self.v[i])?;
+                }
+                self.increment_index(register)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::LoadRegisters(register)) => {
+                for i in 0..=register {
+                    let value = self.read_byte(self.i as usize + i)?;
+                    self.set_v(i, value)?;
+                }
+                self.increment_index(register)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::LoadBigSprite(register)) => {
+                self.set_i(BIG_FONT_ADDRESS + (self.v[register] & 0x0F) as u16 * 10)?;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::StoreFlags(register)) => {
+                self.rpl[..=register].copy_from_slice(&self.v[..=register]);
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::LoadFlags(register)) => {
+                for i in 0..=register {
+                    self.set_v(i, self.rpl[i])?;
+                }
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::SelectPlanes(planes)) => {
+                self.planes = planes & 0x3;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::LoadAudio) => {
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(self.read_bytes(self.i as usize, 16)?);
+                self.audio_pattern = pattern;
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::SetPitch(register)) => {
+                self.pitch = self.v[register];
+                self.pc.wrapping_add(2)
+            },
+
+            Some(Instruction::NOOP) => {
+                self.pc.wrapping_add(2)
+            },
+
+            None => {
+                let opcode = self.read_bytes(self.pc as usize, 2)?;
+                return Err(EmuError::UnknownOpcode {
+                    opcode: (opcode[0] as u16) << 8 | opcode[1] as u16,
+                    pc: self.pc,
+                });
+            }
+        };
+
+        if !self.watch_hits.is_empty() {
+            for hit in self.watch_hits.iter_mut() {
+                hit.instruction = instruction;
+            }
+            outcome = StepOutcome::Watchpoint;
+        }
+        Ok(outcome)
+    }
+
+    fn read_byte(&self, addr: usize) -> Result<u8, EmuError> {
+        self.memory.get(addr).copied().ok_or(EmuError::MemoryOutOfBounds { addr, pc: self.pc })
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<&[u8], EmuError> {
+        self.memory.get(addr..addr + len).ok_or(EmuError::MemoryOutOfBounds { addr: addr + len - 1, pc: self.pc })
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), EmuError> {
+        self.write(Location::Memory(addr), value as u16)
+    }
+
+    fn set_v(&mut self, register: usize, value: u8) -> Result<(), EmuError> {
+        self.write(Location::Register(register), value as u16)
+    }
+
+    fn set_i(&mut self, value: u16) -> Result<(), EmuError> {
+        self.write(Location::Index, value)
+    }
+
+    // Every write an instruction makes to memory, V0-VF or I ends up here,
+    // so watchpoints see all of them.
+    fn write(&mut self, location: Location, value: u16) -> Result<(), EmuError> {
+        let old = match location {
+            Location::Memory(addr) => match self.memory.get_mut(addr) {
+                Some(byte) => std::mem::replace(byte, value as u8) as u16,
+                None => return Err(EmuError::MemoryOutOfBounds { addr, pc: self.pc }),
+            },
+            Location::Register(register) => std::mem::replace(&mut self.v[register], value as u8) as u16,
+            Location::Index => std::mem::replace(&mut self.i, value),
+        };
+        if self.watches.iter().any(|watch| watch.matches(location)) {
+            self.watch_hits.push(WatchHit { pc: self.pc, instruction: None, location, old, new: value });
+        }
+        Ok(())
+    }
+
+    // Clears only the selected XO-CHIP planes.
+    fn clear_display(&mut self) {
+        let keep = !self.planes;
+        for row in self.display.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= keep;
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // Move the selected planes by dx, dy pixels, whatever scrolls in is blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.planes;
+        let source = self.display;
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    source[from_y as usize][from_x as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.display[y as usize][x as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // Where a skip lands. XO-CHIP has to step over all four bytes of F000 NNNN.
+    fn skip(&self) -> u16 {
+        let next = self.pc as usize + 2;
+        match self.memory.get(next..next + 2) {
+            Some([0xF0, 0x00]) => self.pc.wrapping_add(6),
+            _ => self.pc.wrapping_add(4),
+        }
+    }
+
+    // Vx to Vy inclusive, counting down when x > y.
+    fn register_range(regx: usize, regy: usize) -> Box<dyn Iterator<Item = usize>> {
+        if regx <= regy {
+            Box::new(regx..=regy)
+        } else {
+            Box::new((regy..=regx).rev())
+        }
+    }
+
+    // Where FX55/FX65 leave I depends on the interpreter.
+    fn increment_index(&mut self, register: usize) -> Result<(), EmuError> {
+        match self.quirks.index_increment {
+            IndexIncrement::Unchanged => Ok(()),
+            IndexIncrement::X => self.set_i(self.i.wrapping_add(register as u16)),
+            IndexIncrement::XPlusOne => self.set_i(self.i.wrapping_add(register as u16 + 1)),
+        }
+    }
+}
+
+// 64 bit FNV-1a, plenty to tell ROMs apart.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every preset, in the order the expected values below are listed.
+    const PRESETS: [Quirks; 4] = [Quirks::cosmac_vip(), Quirks::chip48(), Quirks::superchip(), Quirks::modern()];
+
+    // Load `rom` and run its first `instructions` instructions.
+    fn run(quirks: Quirks, rom: &[u8], instructions: u32) -> Emulator {
+        let mut emulator = Emulator::new(quirks);
+        emulator.load_rom(rom).unwrap();
+        let run = emulator.run_frame(instructions);
+        assert_eq!(run.result, Ok(StepOutcome::Executed));
+        emulator
+    }
+
+    // Coordinates of the pixels lit on plane 1, row by row.
+    fn lit(emulator: &Emulator) -> Vec<(usize, usize)> {
+        (0..HIRES_HEIGHT).flat_map(|y| (0..HIRES_WIDTH).map(move |x| (x, y)))
+            .filter(|&(x, y)| emulator.display[y][x] & 1 != 0)
+            .collect()
+    }
+
+    #[test]
+    fn shifts_read_vy_on_the_vip() {
+        // V0 = 1, V1 = 6, V0 >>= 1, V2 = 0x81, V2 <<= 1
+        const ROM: [u8; 10] = [0x60, 0x01, 0x61, 0x06, 0x80, 0x16, 0x62, 0x81, 0x82, 0x1E];
+        for (quirks, expected) in PRESETS.into_iter().zip([(3, 0x0C, 0), (0, 0x02, 1), (0, 0x02, 1), (0, 0x02, 1)]) {
+            let emulator = run(quirks, &ROM, 5);
+            assert_eq!((emulator.v[0], emulator.v[2], emulator.v[0xF]), expected, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn register_transfers_move_i_by_preset() {
+        // I = 0x300, store V0-V2, I = 0x300, load V0-V3
+        const ROM: [u8; 8] = [0xA3, 0x00, 0xF2, 0x55, 0xA3, 0x00, 0xF3, 0x65];
+        for quirks in PRESETS {
+            let mut emulator = Emulator::new(quirks);
+            emulator.load_rom(&ROM).unwrap();
+            emulator.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+            emulator.run_frame(2).result.unwrap();
+            let stored = emulator.i;
+            emulator.run_frame(2).result.unwrap();
+            let loaded = emulator.i;
+
+            let expected = match quirks.index_increment {
+                IndexIncrement::XPlusOne => (0x303, 0x304),
+                IndexIncrement::X => (0x302, 0x303),
+                IndexIncrement::Unchanged => (0x300, 0x300),
+            };
+            assert_eq!((stored, loaded), expected, "{:?}", quirks);
+            assert_eq!(&emulator.memory[0x300..0x304], [1, 2, 3, 0]);
+            assert_eq!(emulator.v[..4], [1, 2, 3, 0]);
+        }
+        assert_eq!(PRESETS.map(|quirks| quirks.index_increment), [IndexIncrement::XPlusOne, IndexIncrement::X, IndexIncrement::Unchanged, IndexIncrement::Unchanged]);
+    }
+
+    #[test]
+    fn jumps_add_vx_on_chip48_and_schip() {
+        // V0 = 4, V2 = 0x10, jump to 0x220 plus V0 or V2
+        const ROM: [u8; 6] = [0x60, 0x04, 0x62, 0x10, 0xB2, 0x20];
+        for (quirks, expected) in PRESETS.into_iter().zip([0x224, 0x230, 0x230, 0x224]) {
+            assert_eq!(run(quirks, &ROM, 3).pc, expected, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_on_the_vip() {
+        for operation in [0x11, 0x12, 0x13] {
+            // VF = 5, V0 = 0x0C, V1 = 0x0A, then OR, AND or XOR
+            let rom = [0x6F, 0x05, 0x60, 0x0C, 0x61, 0x0A, 0x80, operation];
+            for (quirks, expected) in PRESETS.into_iter().zip([0, 5, 5, 5]) {
+                assert_eq!(run(quirks, &rom, 4).v[0xF], expected, "80{:02X} {:?}", operation, quirks);
+            }
+        }
+    }
+
+    #[test]
+    fn the_vip_draws_once_per_frame() {
+        // I = 0x208, draw twice, loop
+        const ROM: [u8; 10] = [0xA2, 0x08, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06, 0x80, 0x00];
+        for (quirks, expected) in PRESETS.into_iter().zip([(2, 0x204), (4, 0x206), (4, 0x206), (4, 0x206)]) {
+            let mut emulator = Emulator::new(quirks);
+            emulator.load_rom(&ROM).unwrap();
+            let run = emulator.run_frame(4);
+            assert_eq!((run.instructions, emulator.pc), expected, "{:?}", quirks);
+            if quirks.display_wait {
+                assert_eq!(run.result, Ok(StepOutcome::WaitingForVBlank));
+                // The held draw goes ahead once the next frame starts.
+                assert_eq!(emulator.run_frame(1).instructions, 1);
+                assert_eq!(emulator.pc, 0x206);
+            }
+            // Drawn twice, so nothing is left.
+            assert_eq!(emulator.display[0][0], 0, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn sprites_clip_at_the_edges_except_on_modern() {
+        // V0 = 60, V1 = 31, I = 0x20A, draw two rows of eight
+        const ROM: [u8; 12] = [0x60, 0x3C, 0x61, 0x1F, 0xA2, 0x0A, 0xD0, 0x12, 0x00, 0x00, 0xFF, 0xFF];
+        for quirks in PRESETS {
+            let emulator = run(quirks, &ROM, 4);
+            let lit = |x: usize, y: usize| emulator.display[y][x] == 1;
+            assert!((60..64).all(|x| lit(x, 31)), "{:?}", quirks);
+            let wrapped = [(0, 31), (3, 31), (60, 0), (63, 0), (0, 0), (3, 0)];
+            assert_eq!(wrapped.map(|(x, y)| lit(x, y)), [!quirks.clip_sprites; 6], "{:?}", quirks);
+            assert!(!lit(4, 31) && !lit(59, 31) && !lit(4, 0));
+        }
+        assert_eq!(PRESETS.map(|quirks| quirks.clip_sprites), [true, true, true, false]);
+    }
+
+    #[test]
+    fn schip_scrolls_the_screen() {
+        // High resolution, V0 = 10, V1 = 10, I = 0x210, draw one pixel, then
+        // scroll down 3, right and left
+        const ROM: [u8; 18] = [0x00, 0xFF, 0x60, 0x0A, 0x61, 0x0A, 0xA2, 0x10, 0xD0, 0x11, 0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x80, 0x00];
+        let mut emulator = run(Quirks::superchip(), &ROM, 6);
+        assert_eq!(lit(&emulator), [(10, 13)]);
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!(lit(&emulator), [(14, 13)]);
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!(lit(&emulator), [(10, 13)]);
+    }
+
+    #[test]
+    fn schip_scrolls_pixels_off_the_screen() {
+        // High resolution, V0 = 2, V1 = 62, I = 0x216, draw, scroll left,
+        // draw, scroll down 2, V0 = 126, draw, scroll right
+        const ROM: [u8; 24] = [
+            0x00, 0xFF, 0x60, 0x02, 0x61, 0x3E, 0xA2, 0x16, 0xD0, 0x11, 0x00, 0xFC,
+            0xD0, 0x11, 0x00, 0xC2, 0x60, 0x7E, 0xD0, 0x11, 0x00, 0xFB, 0x80, 0x00,
+        ];
+        let mut emulator = run(Quirks::superchip(), &ROM, 5);
+        assert_eq!(lit(&emulator), [(2, 62)]);
+        for (instructions, expected) in [(1, vec![]), (1, vec![(2, 62)]), (1, vec![]), (2, vec![(126, 62)]), (1, vec![])] {
+            emulator.run_frame(instructions).result.unwrap();
+            assert_eq!(lit(&emulator), expected, "at {:#05X}", emulator.pc);
+        }
+    }
+
+    #[test]
+    fn schip_switches_resolution() {
+        // V0 = 100, V1 = 40, I = 0x210, draw, low resolution, draw, high resolution, draw
+        const ROM: [u8; 18] = [0x60, 0x64, 0x61, 0x28, 0xA2, 0x10, 0xD0, 0x11, 0x00, 0xFE, 0xD0, 0x11, 0x00, 0xFF, 0xD0, 0x11, 0x80, 0x00];
+        let mut emulator = run(Quirks::superchip(), &ROM, 4);
+        // Low resolution wraps the position to 64x32.
+        assert!(!emulator.hires());
+        assert_eq!((emulator.width(), emulator.height()), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        assert_eq!(lit(&emulator), [(36, 8)]);
+
+        emulator.run_frame(2).result.unwrap();
+        assert_eq!(lit(&emulator), [(36, 8)]);
+        emulator.run_frame(1).result.unwrap();
+        assert!(emulator.hires());
+        assert_eq!((emulator.width(), emulator.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        // Switching clears the screen.
+        assert_eq!(lit(&emulator), []);
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!(lit(&emulator), [(100, 40)]);
+
+        emulator.pc = 0x208;
+        emulator.run_frame(1).result.unwrap();
+        assert!(!emulator.hires());
+        assert_eq!(lit(&emulator), []);
+    }
+
+    #[test]
+    fn schip_draws_16_by_16_sprites() {
+        // High resolution, V0 = 120, V1 = 2, I = 0x20C, draw 16x16 twice
+        const ROM: [u8; 12] = [0x00, 0xFF, 0x60, 0x78, 0x61, 0x02, 0xA2, 0x0C, 0xD0, 0x10, 0xD0, 0x10];
+        let mut sprite = [0; 32];
+        // A frame around the edge, and one pixel in the middle.
+        sprite[..2].copy_from_slice(&[0xFF, 0xFF]);
+        sprite[30..].copy_from_slice(&[0xFF, 0xFF]);
+        for row in 1..15 {
+            sprite[row * 2..row * 2 + 2].copy_from_slice(&[0x80, 0x01]);
+        }
+        sprite[16] |= 0x01;
+
+        let mut emulator = Emulator::new(Quirks::superchip());
+        emulator.load_rom(&[&ROM[..], &sprite].concat()).unwrap();
+        emulator.run_frame(5).result.unwrap();
+        let pixels = lit(&emulator);
+        // Clipped at the right edge, so only columns 120 to 127 are drawn.
+        assert_eq!(pixels.len(), 8 + 8 + 14 + 1);
+        assert!(pixels.iter().all(|&(x, y)| (120..128).contains(&x) && (2..18).contains(&y)));
+        assert!(pixels.contains(&(127, 10)) && pixels.contains(&(120, 17)));
+        assert_eq!(emulator.v[0xF], 0);
+
+        // Drawing it again erases it and reports the collision.
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!(lit(&emulator), []);
+        assert_eq!(emulator.v[0xF], 1);
+
+        // Unclipped, all sixteen columns.
+        emulator.v[0] = 0;
+        emulator.pc = 0x20A;
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!(lit(&emulator).len(), 16 + 16 + 14 * 2 + 1);
+        assert!(lit(&emulator).contains(&(15, 17)));
+    }
+
+    #[test]
+    fn schip_big_font() {
+        // V0 = 0xA, I = the big A, draw it 10 rows high
+        const ROM: [u8; 6] = [0x60, 0x0A, 0xF0, 0x30, 0xD1, 0x1A];
+        let emulator = run(Quirks::superchip(), &ROM, 3);
+        assert_eq!(emulator.i, BIG_FONT_ADDRESS + 10 * 10);
+        assert_eq!(emulator.memory[emulator.i as usize..emulator.i as usize + 10], BIG_CHARACTERS[0xA]);
+        for (y, &row) in BIG_CHARACTERS[0xA].iter().enumerate() {
+            let drawn = (0..8).fold(0u8, |byte, x| byte << 1 | emulator.display[y][x]);
+            assert_eq!(drawn, row, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn schip_keeps_flags_in_rpl() {
+        // Store V0-V3, clear V0-V4, load V0-V2
+        const ROM: [u8; 14] = [0xF3, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0x63, 0x00, 0x64, 0x00, 0xF2, 0x85];
+        let mut emulator = Emulator::new(Quirks::superchip());
+        emulator.load_rom(&ROM).unwrap();
+        emulator.v[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+        emulator.run_frame(7).result.unwrap();
+        assert_eq!(emulator.rpl[..5], [1, 2, 3, 4, 0]);
+        assert_eq!(emulator.v[..5], [1, 2, 3, 0, 0]);
+        // Memory is left alone.
+        assert_eq!(emulator.i, 0x200);
+        assert_eq!(emulator.memory[0x200..0x202], [0xF3, 0x75]);
+    }
+
+    #[test]
+    fn xochip_draws_on_the_selected_planes() {
+        // Plane 2, I = 0x218, draw; both planes, draw; plane 1, clear;
+        // no planes, draw
+        const ROM: [u8; 26] = [
+            0xF2, 0x01, 0xA2, 0x18, 0xD0, 0x11, 0xF3, 0x01, 0xD0, 0x11, 0xF1, 0x01,
+            0x00, 0xE0, 0xF0, 0x01, 0xD0, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0xC0,
+        ];
+        let mut emulator = run(Quirks::modern(), &ROM, 3);
+        assert_eq!(emulator.display[0][..3], [2, 0, 0]);
+        assert_eq!(emulator.v[0xF], 0);
+
+        // Plane 1 gets 0x80, plane 2 the byte after it.
+        emulator.run_frame(2).result.unwrap();
+        assert_eq!(emulator.display[0][..3], [1, 2, 0]);
+        assert_eq!(emulator.v[0xF], 1);
+
+        // Only plane 1 is cleared.
+        emulator.run_frame(2).result.unwrap();
+        assert_eq!(emulator.display[0][..3], [0, 2, 0]);
+
+        emulator.run_frame(2).result.unwrap();
+        assert_eq!(emulator.display[0][..3], [0, 2, 0]);
+        assert_eq!(emulator.v[0xF], 0);
+    }
+
+    #[test]
+    fn xochip_saves_and_loads_register_ranges() {
+        // I = 0x300, save V1-V3, save V6-V4 at 0x310, load V7-V9, load VC-VA
+        const ROM: [u8; 12] = [0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x56, 0x42, 0x57, 0x93, 0x5C, 0xA3];
+        let mut emulator = Emulator::new(Quirks::cosmac_vip());
+        emulator.load_rom(&ROM).unwrap();
+        emulator.v[1..7].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        emulator.run_frame(4).result.unwrap();
+        assert_eq!(emulator.memory[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(emulator.memory[0x310..0x314], [6, 5, 4, 0]);
+
+        emulator.run_frame(2).result.unwrap();
+        assert_eq!(emulator.v[7..10], [6, 5, 4]);
+        assert_eq!(emulator.v[10..13], [4, 5, 6]);
+        // Unlike FX55/FX65, I stays put even on the VIP preset.
+        assert_eq!(emulator.i, 0x310);
+    }
+
+    #[test]
+    fn xochip_loads_long_addresses() {
+        // I = 0xFFF0, load V0-V1, I = 0x1234
+        const ROM: [u8; 10] = [0xF0, 0x00, 0xFF, 0xF0, 0xF1, 0x65, 0xF0, 0x00, 0x12, 0x34];
+        let mut emulator = Emulator::new(Quirks::modern());
+        emulator.load_rom(&ROM).unwrap();
+        emulator.memory[0xFFF0..0xFFF2].copy_from_slice(&[0xAB, 0xCD]);
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!((emulator.i, emulator.pc), (0xFFF0, 0x204));
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!(emulator.v[..2], [0xAB, 0xCD]);
+        emulator.run_frame(1).result.unwrap();
+        assert_eq!((emulator.i, emulator.pc), (0x1234, 0x20A));
+    }
+
+    #[test]
+    fn skips_step_over_all_of_a_long_load() {
+        for skip in [
+            [0x30, 0x00],   // V0 == 0
+            [0x41, 0x00],   // V1 != 0
+            [0x50, 0x20],   // V0 == V2
+            [0x90, 0x10],   // V0 != V1
+            [0xE0, 0xA1],   // key V0 not pressed
+        ] {
+            // V1 = 1, the skip, I = 0x1234, V3 = 1
+            let rom = [0x61, 0x01, skip[0], skip[1], 0xF0, 0x00, 0x12, 0x34, 0x63, 0x01];
+            let emulator = run(Quirks::modern(), &rom, 3);
+            assert_eq!((emulator.pc, emulator.i, emulator.v[3]), (0x20A, 0x200, 1), "{:02X}{:02X}", skip[0], skip[1]);
+        }
+
+        // Not skipping runs the long load.
+        let emulator = run(Quirks::modern(), &[0x30, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x63, 0x01], 3);
+        assert_eq!((emulator.pc, emulator.i, emulator.v[3]), (0x208, 0x1234, 1));
+    }
+
+    #[test]
+    fn calls_past_sixteen_levels_overflow_the_stack() {
+        // Call itself forever
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.load_rom(&[0x22, 0x00]).unwrap();
+        let run = emulator.run_frame(20);
+        assert_eq!(run.result, Err(EmuError::StackOverflow { pc: 0x200 }));
+        assert_eq!(run.instructions, 17);
+        assert_eq!((emulator.sp, emulator.pc, emulator.frames().len()), (16, 0x200, 16));
+    }
+
+    #[test]
+    fn returns_with_an_empty_stack_underflow() {
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.load_rom(&[0x60, 0x01, 0x00, 0xEE]).unwrap();
+        assert_eq!(emulator.run_frame(2).result, Err(EmuError::StackUnderflow { pc: 0x202 }));
+        assert_eq!((emulator.sp, emulator.pc), (0, 0x202));
+    }
+
+    #[test]
+    fn accesses_past_the_end_of_memory_are_out_of_bounds() {
+        // I = 0xFFFF, then store V0-V1, load V0-V1, draw two rows or BCD
+        for operation in [[0xF1, 0x55], [0xF1, 0x65], [0xD0, 0x02], [0xF0, 0x33]] {
+            let mut emulator = Emulator::new(Quirks::default());
+            emulator.load_rom(&[0xF0, 0x00, 0xFF, 0xFF, operation[0], operation[1]]).unwrap();
+            emulator.v[0] = 0x2A;
+            let result = emulator.run_frame(2).result;
+            assert_eq!(result, Err(EmuError::MemoryOutOfBounds { addr: 0x10000, pc: 0x204 }), "{:02X}{:02X}", operation[0], operation[1]);
+            assert_eq!(emulator.pc, 0x204);
+        }
+    }
+
+    #[test]
+    fn undefined_opcodes_are_unknown() {
+        for opcode in [0x5001u16, 0x8008, 0xE000, 0xF0FF] {
+            let mut emulator = Emulator::new(Quirks::default());
+            emulator.load_rom(&[0x60, 0x01, (opcode >> 8) as u8, opcode as u8]).unwrap();
+            let run = emulator.run_frame(5);
+            assert_eq!(run.result, Err(EmuError::UnknownOpcode { opcode, pc: 0x202 }));
+            assert_eq!((run.instructions, emulator.pc), (2, 0x202));
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::fmt;
+
+// Everything that can go wrong while running a program. The interpreter
+// stops on the faulting instruction, so pc still points at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    // CALL with all 16 stack slots in use.
+    StackOverflow { pc: u16 },
+    // RET with an empty stack.
+    StackUnderflow { pc: u16 },
+    // A read or write past the end of memory.
+    MemoryOutOfBounds { addr: usize, pc: u16 },
+    // A word that does not decode to any instruction.
+    UnknownOpcode { opcode: u16, pc: u16 },
+}
+
+impl EmuError {
+    // Address of the instruction that failed.
+    pub fn pc(&self) -> u16 {
+        match *self {
+            EmuError::StackOverflow { pc }
+            | EmuError::StackUnderflow { pc }
+            | EmuError::MemoryOutOfBounds { pc, .. }
+            | EmuError::UnknownOpcode { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::StackOverflow { pc } => write!(f, "stack overflow at {:#05X}", pc),
+            EmuError::StackUnderflow { pc } => write!(f, "return with an empty stack at {:#05X}", pc),
+            EmuError::MemoryOutOfBounds { addr, pc } => write!(f, "memory access to {:#06X} out of bounds at {:#05X}", addr, pc),
+            EmuError::UnknownOpcode { opcode, pc } => write!(f, "unknown opcode {:04X} at {:#05X}", opcode, pc),
+        }
+    }
+}
+
+impl Error for EmuError {}
+
+// What a successful step did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // The instruction ran and pc moved on.
+    Executed,
+    // FX0A is waiting for a key, pc did not move.
+    WaitingForKey,
+    // A draw is waiting for the next frame (display_wait quirk), pc did not move.
+    WaitingForVBlank,
+    // The program ran 00FD, pc stays on it.
+    Exited,
+    // The instruction ran and wrote to a watched location, see Emulator::take_watch_hits.
+    Watchpoint,
+}
@@ -0,0 +1,137 @@
+// Built in hex digit sprites. FX29 points I at the small 4x5 ones and
+// FX30 at the SUPER-CHIP 8x10 ones, both live in the interpreter area.
+pub const FONT_ADDRESS: u16 = 0x050;
+pub const BIG_FONT_ADDRESS: u16 = 0x0A0;
+
+// Every interpreter shipped its own hand drawn digits. Some games print
+// text with them, so picking the right set matters for how they look.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FontSet {
+    CosmacVip,
+    Dream6800,
+    Eti660,
+    #[default]
+    Octo,
+}
+
+impl FontSet {
+    pub fn characters(&self) -> &'static [[u8; 5]; 16] {
+        match self {
+            FontSet::CosmacVip => &VIP_CHARACTERS,
+            FontSet::Dream6800 => &DREAM_6800_CHARACTERS,
+            FontSet::Eti660 => &ETI_660_CHARACTERS,
+            FontSet::Octo => &CHARACTERS,
+        }
+    }
+
+    // Look a font set up by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<FontSet> {
+        match name {
+            "vip" | "cosmac-vip" => Some(FontSet::CosmacVip),
+            "dream6800" | "dream-6800" => Some(FontSet::Dream6800),
+            "eti660" | "eti-660" => Some(FontSet::Eti660),
+            "octo" | "modern" => Some(FontSet::Octo),
+            _ => None,
+        }
+    }
+}
+
+// The set most modern interpreters use, from Octo.
+pub const CHARACTERS: [[u8; 5]; 16] = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0],
+    [0x20, 0x60, 0x20, 0x20, 0x70],
+    [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+    [0xF0, 0x10, 0xF0, 0x10, 0xF0],
+    [0x90, 0x90, 0xF0, 0x10, 0x10],
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+    [0xF0, 0x10, 0x20, 0x40, 0x40],
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+    [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+    [0xF0, 0x90, 0xF0, 0x90, 0x90],
+    [0xE0, 0x90, 0xE0, 0x90, 0xE0],
+    [0xF0, 0x80, 0x80, 0x80, 0xF0],
+    [0xE0, 0x90, 0x90, 0x90, 0xE0],
+    [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x80, 0x80]
+    ];
+
+// The COSMAC VIP interpreter's digits, note the boxy B and D.
+pub const VIP_CHARACTERS: [[u8; 5]; 16] = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0],
+    [0x60, 0x20, 0x20, 0x20, 0x70],
+    [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+    [0xF0, 0x10, 0x70, 0x10, 0xF0],
+    [0xA0, 0xA0, 0xF0, 0x20, 0x20],
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+    [0xF0, 0x10, 0x10, 0x10, 0x10],
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+    [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+    [0xF0, 0x90, 0xF0, 0x90, 0x90],
+    [0xF0, 0x50, 0x70, 0x50, 0xF0],
+    [0xF0, 0x80, 0x80, 0x80, 0xF0],
+    [0xF0, 0x50, 0x50, 0x50, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x80, 0x80]
+    ];
+
+// DREAM 6800, three pixels wide.
+pub const DREAM_6800_CHARACTERS: [[u8; 5]; 16] = [
+    [0xE0, 0xA0, 0xA0, 0xA0, 0xE0],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0xE0, 0x20, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x20, 0xE0, 0x20, 0xE0],
+    [0x80, 0xA0, 0xA0, 0xE0, 0x20],
+    [0xE0, 0x80, 0xE0, 0x20, 0xE0],
+    [0xE0, 0x80, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x20, 0x20, 0x20, 0x20],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0x20, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xA0],
+    [0xC0, 0xA0, 0xE0, 0xA0, 0xC0],
+    [0xE0, 0x80, 0x80, 0x80, 0xE0],
+    [0xC0, 0xA0, 0xA0, 0xA0, 0xC0],
+    [0xE0, 0x80, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x80, 0xC0, 0x80, 0x80]
+    ];
+
+// ETI-660, also three pixels wide with lower case b and d.
+pub const ETI_660_CHARACTERS: [[u8; 5]; 16] = [
+    [0xE0, 0xA0, 0xA0, 0xA0, 0xE0],
+    [0x20, 0x20, 0x20, 0x20, 0x20],
+    [0xE0, 0x20, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x20, 0xE0, 0x20, 0xE0],
+    [0xA0, 0xA0, 0xE0, 0x20, 0x20],
+    [0xE0, 0x80, 0xE0, 0x20, 0xE0],
+    [0xE0, 0x80, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x20, 0x20, 0x20, 0x20],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0x20, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xA0],
+    [0x80, 0x80, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x80, 0x80, 0x80, 0xE0],
+    [0x20, 0x20, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x80, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x80, 0xC0, 0x80, 0x80]
+    ];
+
+// SUPER-CHIP 8x10 digits for FX30. SCHIP 1.1 only has 0-9, the letters are from Octo.
+pub const BIG_CHARACTERS: [[u8; 10]; 16] = [
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+    [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+    [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0]
+    ];
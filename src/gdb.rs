@@ -0,0 +1,458 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debugger::{Debugger, Stop, STEPS_BETWEEN_POLLS};
+use crate::emulator::Emulator;
+use crate::error::EmuError;
+use crate::instruction::Address;
+
+/*
+GDB remote serial protocol stub. Listens on localhost, takes one debugger
+connection and runs the emulator for it until it detaches or kills us.
+
+Registers, in `g` packet order, 16 bit ones little endian:
+|- V0 - VF          8 bit
+|- I                16 bit
+|- PC               16 bit
+|- SP               8 bit
+|- DT, ST           8 bit timers
+
+The whole 64K of memory is target memory. Breakpoints are software
+breakpoints kept by the Debugger, memory is never patched.
+*/
+
+const REGISTER_COUNT: usize = 21;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.cpu">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// Wait for gdb on 127.0.0.1:`port` and serve it until it goes away.
+pub fn serve(emulator: &mut Emulator, debugger: &mut Debugger, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    Session { stream, emulator, debugger, pending: Vec::new() }.run()
+}
+
+struct Session<'a> {
+    stream: TcpStream,
+    emulator: &'a mut Emulator,
+    debugger: &'a mut Debugger,
+    pending: Vec<u8>,       // Read from the socket but not handled yet
+}
+
+impl Session<'_> {
+
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.first() {
+                Some(b'c') => self.resume(&packet[1..])?,
+                Some(b's') => {
+                    self.jump_to(&packet[1..]);
+                    let stop = self.debugger.step(self.emulator);
+                    stop_reply(&stop)
+                },
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                },
+                _ => handle(self.emulator, self.debugger, &String::from_utf8_lossy(&packet)),
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    // `c [addr]`: run until a breakpoint, the program stops, or gdb sends Ctrl-C.
+    fn resume(&mut self, address: &[u8]) -> io::Result<String> {
+        self.jump_to(address);
+        loop {
+            if let Some(stop) = self.debugger.resume_for(self.emulator, STEPS_BETWEEN_POLLS) {
+                return Ok(stop_reply(&stop));
+            }
+            if self.interrupted()? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    // `c` and `s` may say where to continue from.
+    fn jump_to(&mut self, address: &[u8]) {
+        if let Some(address) = std::str::from_utf8(address).ok().and_then(|address| Address::from_str_radix(address, 16).ok()) {
+            self.emulator.pc = address;
+        }
+    }
+
+    // Did gdb send the 0x03 interrupt byte while we were running?
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 256];
+        let read = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(len) => {
+                let interrupt = buffer[..len].contains(&0x03);
+                self.pending.extend(buffer[..len].iter().filter(|&&byte| byte != 0x03));
+                Ok(interrupt)
+            },
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if !self.pending.is_empty() {
+            return Ok(Some(self.pending.remove(0)));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // The next `$data#cs` packet, acknowledged. None once gdb hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // Skip acks and stray interrupts until a packet starts.
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => {},
+                    None => return Ok(None),
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let checksum = match (self.read_byte()?, self.read_byte()?) {
+                (Some(high), Some(low)) => [high, low],
+                _ => return Ok(None),
+            };
+            if let Some(data) = decode_packet(&data, &checksum) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(data));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.stream.write_all(&encode_packet(data.as_bytes()))?;
+        self.stream.flush()
+    }
+}
+
+// Every packet that doesn't run the program.
+fn handle(emulator: &mut Emulator, debugger: &mut Debugger, packet: &str) -> String {
+    match packet.as_bytes().first() {
+        Some(b'?') => "S05".to_string(),
+        Some(b'g') => {
+            (0..REGISTER_COUNT).map(|register| read_register(emulator, register)).collect()
+        },
+        Some(b'G') => {
+            let mut data = match decode_hex(&packet[1..]) {
+                Some(data) => data.into_iter(),
+                None => return "E01".to_string(),
+            };
+            for register in 0..REGISTER_COUNT {
+                let bytes: Vec<u8> = data.by_ref().take(register_size(register)).collect();
+                if bytes.len() == register_size(register) {
+                    write_register(emulator, register, &bytes);
+                }
+            }
+            "OK".to_string()
+        },
+        Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+            Ok(register) if register < REGISTER_COUNT => read_register(emulator, register),
+            _ => "E01".to_string(),
+        },
+        Some(b'P') => {
+            let parsed = packet[1..].split_once('=').and_then(|(register, value)| {
+                Some((usize::from_str_radix(register, 16).ok()?, decode_hex(value)?))
+            });
+            match parsed {
+                Some((register, bytes)) if register < REGISTER_COUNT && bytes.len() == register_size(register) => {
+                    write_register(emulator, register, &bytes);
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            }
+        },
+        Some(b'm') => {
+            let range = parse_range(&packet[1..]).and_then(|(address, len)| emulator.memory.get(address..address.checked_add(len)?));
+            match range {
+                Some(bytes) => encode_hex(bytes),
+                None => "E01".to_string(),
+            }
+        },
+        Some(b'M') => {
+            let parsed = packet[1..].split_once(':').and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)));
+            match parsed {
+                Some(((address, len), data)) if data.len() == len && address.saturating_add(len) <= emulator.memory.len() => {
+                    emulator.memory[address..address + len].copy_from_slice(&data);
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            }
+        },
+        Some(action @ (b'Z' | b'z')) if packet[1..].starts_with("0,") => {
+            match packet[3..].split(',').next().and_then(|address| Address::from_str_radix(address, 16).ok()) {
+                Some(address) => {
+                    if *action == b'Z' {
+                        debugger.add_breakpoint(address);
+                    } else {
+                        debugger.remove_breakpoint(address);
+                    }
+                    "OK".to_string()
+                },
+                None => "E01".to_string(),
+            }
+        },
+        Some(b'H') => "OK".to_string(),
+        _ if packet.starts_with("qSupported") => {
+            "PacketSize=4000;qXfer:features:read+;swbreak+".to_string()
+        },
+        _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+            match parse_range(&packet["qXfer:features:read:target.xml:".len()..]) {
+                Some((offset, len)) => {
+                    let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+                    let chunk: String = rest.chars().take(len).collect();
+                    let more = if chunk.len() < rest.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, chunk)
+                },
+                None => "E01".to_string(),
+            }
+        },
+        _ if packet == "qAttached" => "1".to_string(),
+        _ if packet == "qC" => "QC1".to_string(),
+        _ if packet == "qfThreadInfo" => "m1".to_string(),
+        _ if packet == "qsThreadInfo" => "l".to_string(),
+        // Anything we don't know gets the empty reply, gdb falls back.
+        _ => String::new(),
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Exited => "W00".to_string(),
+        Stop::Breakpoint(_) => "T05swbreak:;".to_string(),
+        Stop::Error(EmuError::MemoryOutOfBounds { .. }) => "S0B".to_string(),
+        Stop::Error(EmuError::UnknownOpcode { .. }) => "S04".to_string(),
+        Stop::Error(_) => "S06".to_string(),
+        // Steps, watchpoints, key waits and jumps to self are plain traps.
+        _ => "S05".to_string(),
+    }
+}
+
+fn register_size(register: usize) -> usize {
+    match register {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(emulator: &Emulator, register: usize) -> String {
+    match register {
+        0..=15 => format!("{:02x}", emulator.v[register]),
+        16 => encode_hex(&emulator.i.to_le_bytes()),
+        17 => encode_hex(&emulator.pc.to_le_bytes()),
+        18 => format!("{:02x}", emulator.sp),
+        19 => format!("{:02x}", emulator.delay_timer),
+        _ => format!("{:02x}", emulator.sound_timer),
+    }
+}
+
+fn write_register(emulator: &mut Emulator, register: usize, bytes: &[u8]) {
+    match register {
+        0..=15 => emulator.v[register] = bytes[0],
+        16 => emulator.i = u16::from_le_bytes([bytes[0], bytes[1]]),
+        17 => emulator.pc = u16::from_le_bytes([bytes[0], bytes[1]]),
+        // The stack only has 16 slots.
+        18 => {
+            emulator.sp = bytes[0].min(16);
+            emulator.sync_frames();
+        },
+        19 => emulator.delay_timer = bytes[0],
+        _ => emulator.sound_timer = bytes[0],
+    }
+}
+
+// `addr,len` in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, len) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// `$data#cs`, with the bytes that mean something to the framing escaped as
+// `}` and the byte xor 0x20. The checksum covers the escaped data.
+fn encode_packet(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            escaped.extend([b'}', byte ^ 0x20]);
+        } else {
+            escaped.push(byte);
+        }
+    }
+    let mut packet = vec![b'$'];
+    packet.extend_from_slice(&escaped);
+    packet.extend(format!("#{:02x}", checksum_of(&escaped)).bytes());
+    packet
+}
+
+// The data between `$` and `#`, unescaped, if the two hex digits after the
+// `#` are its checksum.
+fn decode_packet(escaped: &[u8], checksum: &[u8; 2]) -> Option<Vec<u8>> {
+    let checksum = decode_hex(std::str::from_utf8(checksum).ok()?)?;
+    if checksum != [checksum_of(escaped)] {
+        return None;
+    }
+    let mut data = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'}' {
+            data.push(bytes.next()? ^ 0x20);
+        } else {
+            data.push(byte);
+        }
+    }
+    Some(data)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use crate::scheduler::Scheduler;
+
+    fn machine() -> (Emulator, Debugger) {
+        (Emulator::new(Quirks::default()), Debugger::new(Scheduler::new(10)))
+    }
+
+    #[test]
+    fn packets_are_checksummed_and_escaped() {
+        assert_eq!(encode_packet(b"OK"), b"$OK#9a");
+        assert_eq!(encode_packet(b""), b"$#00");
+        // `}` then the byte xor 0x20, summed as sent.
+        assert_eq!(encode_packet(b"a#b$c}d*"), b"$a}\x03b}\x04c}]d}\x0a#ec");
+
+        for data in [&b"m200,4"[..], b"", b"#$}*", b"\x00\xff}}"] {
+            let packet = encode_packet(data);
+            let (escaped, checksum) = packet[1..].split_at(packet.len() - 4);
+            assert_eq!(decode_packet(escaped, checksum[1..].try_into().unwrap()).as_deref(), Some(data));
+        }
+
+        assert_eq!(decode_packet(b"OK", b"9a").as_deref(), Some(&b"OK"[..]));
+        assert_eq!(decode_packet(b"OK", b"9b"), None);
+        assert_eq!(decode_packet(b"OK", b"zz"), None);
+        // An escape with nothing after it.
+        assert_eq!(decode_packet(b"}", b"7d"), None);
+    }
+
+    #[test]
+    fn registers_are_read_in_target_order() {
+        let (mut emulator, mut debugger) = machine();
+        emulator.v[0] = 0x12;
+        emulator.v[15] = 0xAB;
+        emulator.i = 0x1234;
+        emulator.pc = 0x0246;
+        emulator.delay_timer = 7;
+        emulator.sound_timer = 9;
+
+        let reply = handle(&mut emulator, &mut debugger, "g");
+        assert_eq!(reply.len(), 2 * (16 + 2 + 2 + 1 + 2));
+        assert_eq!(&reply[..2], "12");
+        assert_eq!(&reply[30..32], "ab");
+        assert_eq!(&reply[32..], "34124602000709");
+        assert_eq!(handle(&mut emulator, &mut debugger, "p11"), "4602");
+        assert_eq!(handle(&mut emulator, &mut debugger, "p15"), "E01");
+    }
+
+    #[test]
+    fn memory_is_read_and_written() {
+        let (mut emulator, mut debugger) = machine();
+        emulator.load_rom(&[0x60, 0x12, 0x70, 0x01]).unwrap();
+
+        assert_eq!(handle(&mut emulator, &mut debugger, "m200,4"), "60127001");
+        assert_eq!(handle(&mut emulator, &mut debugger, "m202,0"), "");
+        assert_eq!(handle(&mut emulator, &mut debugger, "M201,2:abcd"), "OK");
+        assert_eq!(&emulator.memory[0x200..0x204], [0x60, 0xAB, 0xCD, 0x01]);
+
+        // The end of memory, and past it.
+        assert_eq!(handle(&mut emulator, &mut debugger, "Mfffe,2:0102"), "OK");
+        assert_eq!(handle(&mut emulator, &mut debugger, "mfffe,2"), "0102");
+        assert_eq!(handle(&mut emulator, &mut debugger, "mffff,2"), "E01");
+        assert_eq!(handle(&mut emulator, &mut debugger, "Mffff,2:0102"), "E01");
+        // Length and data disagree, or the data isn't hex.
+        assert_eq!(handle(&mut emulator, &mut debugger, "M200,2:01"), "E01");
+        assert_eq!(handle(&mut emulator, &mut debugger, "M200,1:zz"), "E01");
+        assert_eq!(&emulator.memory[0x200..0x202], [0x60, 0xAB]);
+        assert_eq!(handle(&mut emulator, &mut debugger, "mzz,1"), "E01");
+    }
+
+    #[test]
+    fn breakpoints_are_set_and_cleared() {
+        let (mut emulator, mut debugger) = machine();
+        assert_eq!(handle(&mut emulator, &mut debugger, "Z0,204,2"), "OK");
+        assert_eq!(handle(&mut emulator, &mut debugger, "Z0,2a0,2"), "OK");
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x204, 0x2A0]);
+
+        assert_eq!(handle(&mut emulator, &mut debugger, "z0,204,2"), "OK");
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x2A0]);
+
+        assert_eq!(handle(&mut emulator, &mut debugger, "Z0,10000,2"), "E01");
+        assert_eq!(handle(&mut emulator, &mut debugger, "Z0,,2"), "E01");
+        // Hardware breakpoints aren't supported, gdb gets the empty reply.
+        assert_eq!(handle(&mut emulator, &mut debugger, "Z1,204,2"), "");
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x2A0]);
+    }
+}
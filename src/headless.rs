@@ -0,0 +1,197 @@
+use serde_json::{json, Value};
+
+use crate::emulator::Emulator;
+use crate::error::{EmuError, StepOutcome};
+use crate::scheduler::Scheduler;
+
+/*
+Runs a ROM with no frontend for a fixed number of frames or cycles, for CI.
+
+Keys are scripted by frame. `<frame>:<key>` presses a key at the start of
+that frame and `<frame>:-<key>` lets go of it, separated by spaces or commas:
+
+    60:5 64:-5 120:A 121:-A
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyEvent {
+    frame: u64,
+    key: u8,
+    down: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,      // In frame order
+}
+
+impl KeyScript {
+
+    pub fn parse(text: &str) -> Result<KeyScript, String> {
+        let mut events = Vec::new();
+        for event in text.split([' ', ',', '\n', '\t']).filter(|event| !event.is_empty()) {
+            let parsed = event.split_once(':').and_then(|(frame, key)| {
+                let (key, down) = match key.strip_prefix('-') {
+                    Some(key) => (key, false),
+                    None => (key, true),
+                };
+                let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16)?;
+                Some(KeyEvent { frame: frame.parse().ok()?, key, down })
+            });
+            match parsed {
+                Some(event) => events.push(event),
+                None => return Err(format!("bad key event `{}`, expected frame:key or frame:-key", event)),
+            }
+        }
+        events.sort_by_key(|event| event.frame);
+        Ok(KeyScript { events })
+    }
+
+    fn apply(&self, frame: u64, emulator: &mut Emulator) {
+        for event in self.events.iter().filter(|event| event.frame == frame) {
+            if event.down {
+                emulator.key_down(event.key);
+            } else {
+                emulator.key_up(event.key);
+            }
+        }
+    }
+}
+
+// How long to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Length {
+    Frames(u64),
+    // Instructions; the last frame gets whatever is left over.
+    Cycles(u64),
+}
+
+// How a run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub frames: u64,
+    pub cycles: u64,                // Instructions that ran, a failing one included
+    pub exited: bool,               // 00FD ended it early
+    pub error: Option<EmuError>,
+}
+
+// Frames get the same instruction budgets as in the window frontend.
+pub fn run(emulator: &mut Emulator, scheduler: &mut Scheduler, length: Length, keys: &KeyScript) -> Summary {
+    let mut summary = Summary { frames: 0, cycles: 0, exited: false, error: None };
+    loop {
+        let budget = match length {
+            Length::Frames(frames) if summary.frames < frames => scheduler.next_budget() as u64,
+            Length::Cycles(cycles) if summary.cycles < cycles => (cycles - summary.cycles).min(scheduler.next_budget() as u64),
+            _ => break,
+        };
+        keys.apply(summary.frames, emulator);
+        let run = emulator.run_frame(budget as u32);
+        summary.frames += 1;
+        summary.cycles += run.instructions as u64;
+        match run.result {
+            Ok(StepOutcome::Exited) => {
+                summary.exited = true;
+                break;
+            },
+            Ok(_) => {},
+            Err(error) => {
+                summary.error = Some(error);
+                break;
+            },
+        }
+    }
+    summary
+}
+
+// The machine at the end of a run:
+//
+//     {"pc": 520, "i": 768, "v": [0, 5, ...], "sp": 1, "stack": [514],
+//      "delay_timer": 0, "sound_timer": 0, "hires": false,
+//      "frames": 600, "cycles": 6600, "exited": false, "error": null}
+pub fn state_json(emulator: &Emulator, summary: &Summary) -> Value {
+    json!({
+        "pc": emulator.pc,
+        "i": emulator.i,
+        "v": emulator.v,
+        "sp": emulator.sp,
+        "stack": emulator.stack[..emulator.sp as usize],
+        "delay_timer": emulator.delay_timer,
+        "sound_timer": emulator.sound_timer,
+        "hires": emulator.hires,
+        "frames": summary.frames,
+        "cycles": summary.cycles,
+        "exited": summary.exited,
+        "error": summary.error.as_ref().map(|error| error.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn emulator(quirks: Quirks, rom: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new(quirks);
+        emulator.load_rom(rom).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn key_scripts() {
+        let script = KeyScript::parse("120:a, 60:5\n64:-5\t121:-A").unwrap();
+        assert_eq!(script.events, [
+            KeyEvent { frame: 60, key: 0x5, down: true },
+            KeyEvent { frame: 64, key: 0x5, down: false },
+            KeyEvent { frame: 120, key: 0xA, down: true },
+            KeyEvent { frame: 121, key: 0xA, down: false },
+        ]);
+        assert!(KeyScript::parse("").unwrap().events.is_empty());
+        for bad in ["60", "60:10", "x:5", "60:-", ":5"] {
+            assert!(KeyScript::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn keys_are_pressed_on_their_frame() {
+        // V0 := key, count in V1 while it is held, exit once it is let go.
+        let mut emulator = emulator(Quirks::default(), &[0xF0, 0x0A, 0x71, 0x01, 0xE0, 0xA1, 0x12, 0x02, 0x00, 0xFD]);
+        let summary = run(&mut emulator, &mut Scheduler::new(4), Length::Frames(100), &KeyScript::parse("10:7 20:-7").unwrap());
+        assert!(summary.exited);
+        assert_eq!(summary.frames, 21);
+        assert_eq!(emulator.v[0], 7);
+        assert!(emulator.v[1] > 0);
+    }
+
+    #[test]
+    fn frames_and_cycles() {
+        // Count in V0 forever.
+        let rom = [0x70, 0x01, 0x12, 0x00];
+        let mut counting = emulator(Quirks::default(), &rom);
+        let summary = run(&mut counting, &mut Scheduler::with_hz(1000), Length::Frames(60), &KeyScript::default());
+        assert_eq!((summary.frames, summary.cycles), (60, 1000));
+
+        let mut counting = emulator(Quirks::default(), &rom);
+        let summary = run(&mut counting, &mut Scheduler::new(11), Length::Cycles(100), &KeyScript::default());
+        assert_eq!((summary.frames, summary.cycles), (10, 100));
+        assert_eq!(counting.v[0], 50);
+    }
+
+    #[test]
+    fn cycles_count_what_ran() {
+        // Two instructions, then a return with nothing on the stack.
+        let mut failing = emulator(Quirks::default(), &[0x60, 0x01, 0x70, 0x01, 0x00, 0xEE]);
+        let summary = run(&mut failing, &mut Scheduler::new(11), Length::Frames(5), &KeyScript::default());
+        assert_eq!((summary.frames, summary.cycles), (1, 3));
+        assert!(matches!(summary.error, Some(EmuError::StackUnderflow { pc: 0x204 })));
+
+        // Draw in a loop, one draw per frame with display_wait.
+        let mut waiting = emulator(Quirks::cosmac_vip(), &[0xD0, 0x11, 0x70, 0x01, 0x12, 0x00]);
+        let summary = run(&mut waiting, &mut Scheduler::new(11), Length::Cycles(30), &KeyScript::default());
+        assert_eq!(summary.cycles, 30);
+        assert_eq!(waiting.v[0], 10);
+
+        let mut exiting = emulator(Quirks::default(), &[0x00, 0xFD]);
+        let summary = run(&mut exiting, &mut Scheduler::new(11), Length::Frames(5), &KeyScript::default());
+        assert_eq!((summary.frames, summary.cycles, summary.exited), (1, 1, true));
+    }
+}
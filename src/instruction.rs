@@ -2,7 +2,7 @@
 pub type Address = u16;
 pub type Register = usize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpCode(pub u16);
 
 impl OpCode {
@@ -34,11 +34,28 @@ impl OpCode {
 
 
 // Instructions for chip-8
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     ClearDisplay,                       // 00E0 - CLS
     Return,                             // 00EE - RET
 
+    // SUPER-CHIP 1.1
+    ScrollDown(u8),                     // 00CN - SCD nibble
+    ScrollRight,                        // 00FB - SCR
+    ScrollLeft,                         // 00FC - SCL
+    Exit,                               // 00FD - EXIT
+    LowRes,                             // 00FE - LOW
+    HighRes,                            // 00FF - HIGH
+
+    // XO-CHIP
+    ScrollUp(u8),                       // 00DN - SCU nibble
+    SaveRange(Register, Register),      // 5XY2 - SAVE Vx - Vy
+    LoadRange(Register, Register),      // 5XY3 - LOAD Vx - Vy
+    LoadILong(Address),                 // F000 NNNN - LD I, long addr
+    SelectPlanes(u8),                   // FN01 - PLANE n
+    LoadAudio,                          // F002 - AUDIO
+    SetPitch(Register),                 // FX3A - PITCH Vx
+
     Jump(Address),                      // 1NNN - JP addr
     Call(Address),                      // 2NNN - CALL addr
     SkipIfEqualsByte(Register, u8),            // 3XNN - SE Vx, byte
@@ -53,9 +70,9 @@ pub enum Instruction {
     Xor(Register, Register),            // 8XY3 - XOR Vx, Vy
     Add(Register, Register),          // 8XY4 - ADD Vx, Vy
     Sub(Register, Register),          // 8XY5 - SUB Vx, Vy
-    ShiftRight(Register),               // 8XY6 - SHR Vx {, Vy}
+    ShiftRight(Register, Register),     // 8XY6 - SHR Vx {, Vy}
     ReverseSub(Register, Register),          // 8XY7 - SUBN Vx, Vy
-    ShiftLeft(Register),                // 8XYE - SHL Vx {, Vy}
+    ShiftLeft(Register, Register),      // 8XYE - SHL Vx {, Vy}
 
     SkipIfNotEqual(Register, Register), // 9XY0 - SNE Vx, Vy
     LoadI(Address),                     // ANNN - LD I, addr
@@ -75,16 +92,26 @@ pub enum Instruction {
     StoreBCD(Register),                 // FX33 - LD B, Vx
     StoreRegisters(Register),           // FX55 - LD [I], Vx
     LoadRegisters(Register),            // FX65 - LD Vx, [I]
-    NOOP,
+    LoadBigSprite(Register),            // FX30 - LD HF, Vx
+    StoreFlags(Register),               // FX75 - LD R, Vx
+    LoadFlags(Register),                // FX85 - LD Vx, R
+    NOOP,                               // FX69 - NOOP
 }
 
 impl Instruction {
 
     pub fn new(opcode: OpCode) -> Option<Instruction>{
         match opcode.0 & 0xF000 {
-            0x0000 => match opcode.ooon() {
-                0x0000 => Some(Instruction::ClearDisplay),
-                0x000E => Some(Instruction::Return),
+            0x0000 => match opcode.0 {
+                0x00E0 => Some(Instruction::ClearDisplay),
+                0x00EE => Some(Instruction::Return),
+                0x00C0..=0x00CF => Some(Instruction::ScrollDown(opcode.ooon())),
+                0x00D0..=0x00DF => Some(Instruction::ScrollUp(opcode.ooon())),
+                0x00FB => Some(Instruction::ScrollRight),
+                0x00FC => Some(Instruction::ScrollLeft),
+                0x00FD => Some(Instruction::Exit),
+                0x00FE => Some(Instruction::LowRes),
+                0x00FF => Some(Instruction::HighRes),
                 _ => None,
             },
             0x1000 => Some(Instruction::Jump(opcode.onnn())),
@@ -94,7 +121,12 @@ impl Instruction {
                 opcode.oxoo(),
                 opcode.oonn(),
             )),
-            0x5000 => Some(Instruction::SkipIfEqual(opcode.oxoo(), opcode.ooyo())),
+            0x5000 => match opcode.ooon() {
+                0x0000 => Some(Instruction::SkipIfEqual(opcode.oxoo(), opcode.ooyo())),
+                0x0002 => Some(Instruction::SaveRange(opcode.oxoo(), opcode.ooyo())),
+                0x0003 => Some(Instruction::LoadRange(opcode.oxoo(), opcode.ooyo())),
+                _ => None,
+            },
             0x6000 => Some(Instruction::LoadByte(opcode.oxoo(), opcode.oonn())),
             0x7000 => Some(Instruction::AddByte(opcode.oxoo(), opcode.oonn())),
             0x8000 => match opcode.ooon() {
@@ -104,12 +136,12 @@ impl Instruction {
                 0x0003 => Some(Instruction::Xor(opcode.oxoo(), opcode.ooyo())),
                 0x0004 => Some(Instruction::Add(opcode.oxoo(), opcode.ooyo())),
                 0x0005 => Some(Instruction::Sub(opcode.oxoo(), opcode.ooyo())),
-                0x0006 => Some(Instruction::ShiftRight(opcode.oxoo())),
+                0x0006 => Some(Instruction::ShiftRight(opcode.oxoo(), opcode.ooyo())),
                 0x0007 => Some(Instruction::ReverseSub(opcode.oxoo(), opcode.ooyo())),
-                0x000E => Some(Instruction::ShiftLeft(opcode.oxoo())),
+                0x000E => Some(Instruction::ShiftLeft(opcode.oxoo(), opcode.ooyo())),
                 _ => None,
             },
-            0x9000 => Some(Instruction::SkipIfNotEqual(opcode.oxoo(), opcode.ooyo())),
+            0x9000 if opcode.ooon() == 0 => Some(Instruction::SkipIfNotEqual(opcode.oxoo(), opcode.ooyo())),
             0xA000 => Some(Instruction::LoadI(opcode.onnn())),
             0xB000 => Some(Instruction::JumpPlusZero(opcode.onnn())),
             0xC000 => Some(Instruction::Random(opcode.oxoo(), opcode.oonn())),
@@ -124,6 +156,10 @@ impl Instruction {
                 _ => None,
             },
             0xF000 => match opcode.oonn() {
+                // F000 NNNN needs the next word, see Instruction::decode.
+                0x0001 => Some(Instruction::SelectPlanes(opcode.oxoo() as u8)),
+                0x0002 if opcode.oxoo() == 0 => Some(Instruction::LoadAudio),
+                0x003A => Some(Instruction::SetPitch(opcode.oxoo())),
                 0x0007 => Some(Instruction::LoadDelayTimer(opcode.oxoo())),
                 0x000A => Some(Instruction::WaitForKeyPress(opcode.oxoo())),
                 0x0015 => Some(Instruction::SetDelayTimer(opcode.oxoo())),
@@ -133,10 +169,139 @@ impl Instruction {
                 0x0033 => Some(Instruction::StoreBCD(opcode.oxoo())),
                 0x0055 => Some(Instruction::StoreRegisters(opcode.oxoo())),
                 0x0065 => Some(Instruction::LoadRegisters(opcode.oxoo())),
-                0x0069 => Some(Instruction::NOOP),
+                0x0030 => Some(Instruction::LoadBigSprite(opcode.oxoo())),
+                0x0075 => Some(Instruction::StoreFlags(opcode.oxoo())),
+                0x0085 => Some(Instruction::LoadFlags(opcode.oxoo())),
+                0x0069 if opcode.oxoo() == 0 => Some(Instruction::NOOP),
                 _ => None,
             },
             _ => None,
         }
     }
-}
\ No newline at end of file
+
+    // Decode the instruction at address. Everything is one word except the
+    // XO-CHIP F000 NNNN, which carries its address in the following word.
+    pub fn decode(memory: &[u8], address: usize) -> Option<Instruction> {
+        let word = |at: usize| Some((*memory.get(at)? as u16) << 8 | *memory.get(at + 1)? as u16);
+        match word(address)? {
+            0xF000 => Some(Instruction::LoadILong(word(address + 2)?)),
+            opcode => Instruction::new(OpCode(opcode)),
+        }
+    }
+
+    // The opcode for this instruction, the inverse of Instruction::new.
+    // F000 NNNN only gives its first word back, the address follows it.
+    pub fn encode(&self) -> OpCode {
+        let x = |register: Register| (register as u16 & 0xF) << 8;
+        let xy = |regx: Register, regy: Register| x(regx) | (regy as u16 & 0xF) << 4;
+        OpCode(match *self {
+            Instruction::ClearDisplay => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::SaveRange(regx, regy) => 0x5002 | xy(regx, regy),
+            Instruction::LoadRange(regx, regy) => 0x5003 | xy(regx, regy),
+            Instruction::LoadILong(_) => 0xF000,
+            Instruction::SelectPlanes(n) => 0xF001 | (n as u16 & 0xF) << 8,
+            Instruction::LoadAudio => 0xF002,
+            Instruction::SetPitch(regx) => 0xF03A | x(regx),
+            Instruction::Jump(address) => 0x1000 | (address & 0x0FFF),
+            Instruction::Call(address) => 0x2000 | (address & 0x0FFF),
+            Instruction::SkipIfEqualsByte(regx, byte) => 0x3000 | x(regx) | byte as u16,
+            Instruction::SkipIfNotEqualsByte(regx, byte) => 0x4000 | x(regx) | byte as u16,
+            Instruction::SkipIfEqual(regx, regy) => 0x5000 | xy(regx, regy),
+            Instruction::LoadByte(regx, byte) => 0x6000 | x(regx) | byte as u16,
+            Instruction::AddByte(regx, byte) => 0x7000 | x(regx) | byte as u16,
+            Instruction::Move(regx, regy) => 0x8000 | xy(regx, regy),
+            Instruction::Or(regx, regy) => 0x8001 | xy(regx, regy),
+            Instruction::And(regx, regy) => 0x8002 | xy(regx, regy),
+            Instruction::Xor(regx, regy) => 0x8003 | xy(regx, regy),
+            Instruction::Add(regx, regy) => 0x8004 | xy(regx, regy),
+            Instruction::Sub(regx, regy) => 0x8005 | xy(regx, regy),
+            Instruction::ShiftRight(regx, regy) => 0x8006 | xy(regx, regy),
+            Instruction::ReverseSub(regx, regy) => 0x8007 | xy(regx, regy),
+            Instruction::ShiftLeft(regx, regy) => 0x800E | xy(regx, regy),
+            Instruction::SkipIfNotEqual(regx, regy) => 0x9000 | xy(regx, regy),
+            Instruction::LoadI(address) => 0xA000 | (address & 0x0FFF),
+            Instruction::JumpPlusZero(address) => 0xB000 | (address & 0x0FFF),
+            Instruction::Random(regx, byte) => 0xC000 | x(regx) | byte as u16,
+            Instruction::Draw(regx, regy, n) => 0xD000 | xy(regx, regy) | (n as u16 & 0xF),
+            Instruction::SkipIfPressed(regx) => 0xE09E | x(regx),
+            Instruction::SkipIfNotPressed(regx) => 0xE0A1 | x(regx),
+            Instruction::LoadDelayTimer(regx) => 0xF007 | x(regx),
+            Instruction::WaitForKeyPress(regx) => 0xF00A | x(regx),
+            Instruction::SetDelayTimer(regx) => 0xF015 | x(regx),
+            Instruction::SetSoundTimer(regx) => 0xF018 | x(regx),
+            Instruction::AddI(regx) => 0xF01E | x(regx),
+            Instruction::LoadSprite(regx) => 0xF029 | x(regx),
+            Instruction::StoreBCD(regx) => 0xF033 | x(regx),
+            Instruction::StoreRegisters(regx) => 0xF055 | x(regx),
+            Instruction::LoadRegisters(regx) => 0xF065 | x(regx),
+            Instruction::LoadBigSprite(regx) => 0xF030 | x(regx),
+            Instruction::StoreFlags(regx) => 0xF075 | x(regx),
+            Instruction::LoadFlags(regx) => 0xF085 | x(regx),
+            Instruction::NOOP => 0xF069,
+        })
+    }
+
+    // Size of the instruction in bytes.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong(_) => 4,
+            _ => 2,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    // One of every instruction, with operands that use every bit they have.
+    const ALL: [Instruction; 51] = [
+        ClearDisplay, Return, ScrollDown(0xA), ScrollRight, ScrollLeft, Exit, LowRes, HighRes,
+        ScrollUp(0x5), SaveRange(0x1, 0xE), LoadRange(0xE, 0x1), LoadILong(0xBEEF), SelectPlanes(0x3),
+        LoadAudio, SetPitch(0xC), Jump(0xABC), Call(0x123), SkipIfEqualsByte(0x4, 0xFE),
+        SkipIfNotEqualsByte(0xB, 0x01), SkipIfEqual(0x2, 0xD), LoadByte(0xF, 0x80), AddByte(0x0, 0x7F),
+        Move(0x3, 0xC), Or(0x5, 0xA), And(0x6, 0x9), Xor(0x7, 0x8), Add(0x8, 0x7), Sub(0x9, 0x6),
+        ShiftRight(0xA, 0x5), ReverseSub(0xB, 0x4), ShiftLeft(0xC, 0x3), SkipIfNotEqual(0xD, 0x2),
+        LoadI(0xFFF), JumpPlusZero(0x800), Random(0xE, 0x55), Draw(0x1, 0x2, 0xF), SkipIfPressed(0x3),
+        SkipIfNotPressed(0x4), LoadDelayTimer(0x5), WaitForKeyPress(0x6), SetDelayTimer(0x7),
+        SetSoundTimer(0x8), AddI(0x9), LoadSprite(0xA), StoreBCD(0xB), StoreRegisters(0xC),
+        LoadRegisters(0xD), LoadBigSprite(0xE), StoreFlags(0xF), LoadFlags(0x1), NOOP,
+    ];
+
+    #[test]
+    fn every_instruction_round_trips() {
+        for instruction in ALL {
+            let decoded = match instruction {
+                // The address is in the second word, only decode sees it.
+                LoadILong(address) => {
+                    let [high, low] = instruction.encode().0.to_be_bytes();
+                    let [address_high, address_low] = address.to_be_bytes();
+                    Instruction::decode(&[high, low, address_high, address_low], 0)
+                },
+                _ => Instruction::new(instruction.encode()),
+            };
+            assert_eq!(decoded, Some(instruction), "{:04X}", instruction.encode().0);
+        }
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        let mut kinds = std::collections::HashSet::new();
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = Instruction::new(OpCode(opcode)) {
+                assert_eq!(instruction.encode(), OpCode(opcode));
+                kinds.insert(std::mem::discriminant(&instruction));
+            }
+        }
+        // Everything but F000 NNNN, which takes two words.
+        assert_eq!(kinds.len(), ALL.len() - 1);
+    }
+}
@@ -0,0 +1,50 @@
+// Core CHIP-8 interpreter. Nothing in here depends on SDL, the window
+// frontend lives behind the `sdl` feature and the terminal one behind `terminal`.
+pub mod asm;
+pub mod callstack;
+pub mod capture;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod emulator;
+pub mod error;
+pub mod font;
+pub mod gdb;
+pub mod headless;
+pub mod instruction;
+pub mod octo;
+pub mod palette;
+pub mod phosphor;
+pub mod profile;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod scheduler;
+pub mod symbols;
+pub mod trace;
+pub mod watch;
+
+#[cfg(feature = "sdl")]
+pub mod audio;
+#[cfg(feature = "sdl")]
+pub mod display;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+
+pub use crate::callstack::Frame;
+pub use crate::emulator::{Emulator, FrameRun, Screen, HIRES_HEIGHT, HIRES_WIDTH, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use crate::error::{EmuError, StepOutcome};
+pub use crate::font::FontSet;
+pub use crate::instruction::{Instruction, OpCode};
+pub use crate::palette::Palette;
+pub use crate::phosphor::Phosphor;
+pub use crate::profile::Profiler;
+pub use crate::quirks::Quirks;
+pub use crate::rewind::Rewind;
+pub use crate::rng::RandomSource;
+pub use crate::savestate::StateError;
+pub use crate::scheduler::Scheduler;
+pub use crate::symbols::Symbols;
+pub use crate::trace::Tracer;
+pub use crate::watch::{Watch, WatchHit};
@@ -1,101 +1,474 @@
-use std::io::Read;
 use std::fs::File;
 use std::io;
-use rand::random;
-use sdl2::sys::register_t;
+use std::io::BufWriter;
+use std::process::ExitCode;
+
+use chip8::asm::parse_number;
+use chip8::debugger::Debugger;
+use chip8::scheduler::FRAME_RATE;
+use chip8::headless::{KeyScript, Length, Summary};
+use chip8::phosphor::DEFAULT_DECAY;
+use chip8::{Emulator, FontSet, MEMORY_SIZE, Palette, Profiler, Quirks, Scheduler, Symbols, Tracer};
+#[cfg(feature = "sdl")]
+use chip8::Rewind;
+#[cfg(any(feature = "sdl", feature = "terminal"))]
+use chip8::StepOutcome;
+
+#[cfg(feature = "sdl")]
+use chip8::audio::Audio;
+#[cfg(any(feature = "sdl", feature = "terminal"))]
+use chip8::callstack::backtrace;
+#[cfg(feature = "terminal")]
+use chip8::terminal::{Glyphs, Terminal};
+#[cfg(any(feature = "sdl", feature = "terminal"))]
+use chip8::Phosphor;
+#[cfg(feature = "sdl")]
+use chip8::capture::{self, Recorder};
+#[cfg(feature = "sdl")]
+use chip8::savestate;
+#[cfg(feature = "sdl")]
+use chip8::display::*;
+
+#[cfg(feature = "sdl")]
+use sdl2::event::Event;
+#[cfg(feature = "sdl")]
+use sdl2::keyboard::Keycode;
 
-mod instruction;
-use crate::instruction::*;
 
-mod display;
-use crate::display::*;
+fn main() -> io::Result<ExitCode> {
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("disasm") => {
+            args.next();
+            disasm(args)?;
+        },
+        Some("asm") => {
+            args.next();
+            asm(args)?;
+        },
+        Some("octo") => {
+            args.next();
+            octo(args)?;
+        },
+        // Editors start the adapter and talk to it over stdin and stdout.
+        Some("dap") => chip8::dap::serve(io::stdin(), io::stdout())?,
+        _ => return emulate(args),
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-extern crate sdl2;
+// chip8 disasm <rom>
+fn disasm(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let rom = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: chip8 disasm <rom>");
+            std::process::exit(2);
+        }
+    };
+    let rom = std::fs::read(rom)?;
+    if rom.len() > MEMORY_SIZE - 0x200 {
+        eprintln!("ROM does not fit in memory: {} bytes, at most {} load at 0x200", rom.len(), MEMORY_SIZE - 0x200);
+        std::process::exit(1);
+    }
+    print!("{}", chip8::disasm::disassemble(&rom));
+    Ok(())
+}
+
+// chip8 asm <source> [-o <rom>]
+fn asm(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut source = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ => source = Some(arg),
+        }
+    }
+    let source = match source {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: chip8 asm <source> [-o <rom>]");
+            std::process::exit(2);
+        }
+    };
+    let rom = match chip8::asm::assemble_file(&source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    // game.asm -> game.ch8 unless told otherwise
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&source).with_extension("ch8").display().to_string()
+    });
+    std::fs::write(output, rom)
+}
+
+// chip8 octo <source> [-o <rom>] [--labels <file>]
+fn octo(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut source = None;
+    let mut output = None;
+    let mut labels = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            "--labels" => labels = args.next(),
+            _ => source = Some(arg),
+        }
+    }
+    let source = match source {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: chip8 octo <source> [-o <rom>] [--labels <file>]");
+            std::process::exit(2);
+        }
+    };
+    let program = compile_octo(&source);
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&source).with_extension("ch8").display().to_string()
+    });
+    std::fs::write(output, &program.rom)?;
+    if let Some(labels) = labels {
+        std::fs::write(labels, program.label_table())?;
+    }
+    Ok(())
+}
+
+fn compile_octo(source: &str) -> chip8::octo::Program {
+    match chip8::octo::compile_file(source) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Fails with exit code 1 when a headless run hits an emulator error.
+fn emulate(args: impl Iterator<Item = String>) -> io::Result<ExitCode> {
+    let mut args = args.peekable();
+    let mut rom = None;
+    let mut quirks = Quirks::default();
+    let mut scheduler = Scheduler::default();
+    let mut font = FontSet::default();
+    let mut seed = None;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut trace = None;
+    let mut trace_ranges = Vec::new();
+    let mut symbols = None;
+    let mut profile = None;
+    let mut folded = None;
+    let mut terminal = None;
+    let mut headless = false;
+    let mut length = Length::Frames(FRAME_RATE as u64);
+    let mut keys = KeyScript::default();
+    let mut screen = None;
+    let mut dump = None;
+    let mut view = View { palette: 0, scale: DEFAULT_SCALE, fullscreen: false, phosphor: None };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().unwrap_or_default();
+                quirks = Quirks::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("unknown quirks profile `{}` (expected vip, chip48, schip or modern)", name);
+                    std::process::exit(2);
+                });
+            },
+            "--font" => {
+                let name = args.next().unwrap_or_default();
+                font = FontSet::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("unknown font `{}` (expected vip, dream6800, eti660 or octo)", name);
+                    std::process::exit(2);
+                });
+            },
+            "--seed" => seed = Some(number_arg(&arg, args.next())),
+            "--debug" => debug = true,
+            "--gdb" => {
+                let port = number_arg(&arg, args.next());
+                gdb_port = Some(u16::try_from(port).unwrap_or_else(|_| {
+                    eprintln!("--gdb port {} is out of range (at most {})", port, u16::MAX);
+                    std::process::exit(2);
+                }));
+            },
+            "--trace" => trace = args.next(),
+            "--trace-range" => trace_ranges.push(range_arg(&arg, args.next())),
+            "--symbols" => symbols = args.next(),
+            "--profile" => profile = args.next(),
+            "--folded" => folded = args.next(),
+            // The glyphs are optional, so `--terminal game.ch8` keeps the ROM.
+            "--terminal" => terminal = Some(args.next_if(|name| GLYPHS.contains(&name.as_str())).unwrap_or_else(|| GLYPHS[0].to_string())),
+            "--headless" => headless = true,
+            "--frames" => length = Length::Frames(number_arg(&arg, args.next()) as u64),
+            "--cycles" => length = Length::Cycles(number_arg(&arg, args.next()) as u64),
+            "--keys" => {
+                keys = KeyScript::parse(&args.next().unwrap_or_default()).unwrap_or_else(|error| {
+                    eprintln!("--keys: {}", error);
+                    std::process::exit(2);
+                });
+            },
+            "--screen" => screen = args.next(),
+            "--dump" => dump = args.next(),
+            "--palette" => {
+                let name = args.next().unwrap_or_default();
+                view.palette = Palette::NAMES.iter().position(|&known| known == name).unwrap_or_else(|| {
+                    eprintln!("unknown palette `{}` (expected {})", name, Palette::NAMES.join(", "));
+                    std::process::exit(2);
+                });
+            },
+            "--scale" => view.scale = number_arg(&arg, args.next()).max(1) as usize,
+            "--fullscreen" => view.fullscreen = true,
+            "--phosphor" => {
+                let value = args.next().unwrap_or_default();
+                view.phosphor = Some(value.parse().ok().filter(|decay| (0.0..1.0).contains(decay)).unwrap_or_else(|| {
+                    eprintln!("--phosphor expects a decay from 0 to 1, like {}", DEFAULT_DECAY);
+                    std::process::exit(2);
+                }));
+            },
+            "--ipf" => scheduler = Scheduler::new(number_arg(&arg, args.next())),
+            "--hz" => scheduler = Scheduler::with_hz(number_arg(&arg, args.next())),
+            _ => rom = Some(arg),
+        }
+    }
+
+    let rom = match rom {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: chip8 disasm <rom>");
+            eprintln!("       chip8 asm <source> [-o <rom>]");
+            eprintln!("       chip8 octo <source> [-o <rom>] [--labels <file>]");
+            eprintln!("       chip8 dap");
+            eprintln!("       chip8 --headless [--frames n | --cycles n] [--keys script] [--screen out.png|out.pbm] [--dump out.json] <rom>");
+            eprintln!("       chip8 [--quirks vip|chip48|schip|modern] [--font vip|dream6800|eti660|octo] [--ipf n | --hz n] [--seed n] [--debug | --gdb port] [--trace file [--trace-range a-b]] [--symbols file] [--profile file] [--folded file] [--terminal [half|braille]] [--palette name] [--scale n] [--fullscreen] [--phosphor decay] <rom>");
+            std::process::exit(2);
+        }
+    };
+
+    let mut emulator = Emulator::new(quirks);
+    emulator.load_font(font);
+    if let Some(seed) = seed {
+        emulator.seed(seed as u64);
+    }
 
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::time::Duration;
-
-
-const SCREEN_WIDTH: usize = 64;
-const SCREEN_HEIGHT: usize = 32;
-
-
-
-const CHARACTERS: [[u8; 5]; 16] = [
-    [0xF0, 0x90, 0x90, 0x90, 0xF0],
-    [0x20, 0x60, 0x20, 0x20, 0x70],
-    [0xF0, 0x10, 0xF0, 0x80, 0xF0],
-    [0xF0, 0x10, 0xF0, 0x10, 0xF0],
-    [0x90, 0x90, 0xF0, 0x10, 0x10],
-    [0xF0, 0x80, 0xF0, 0x10, 0xF0],
-    [0xF0, 0x80, 0xF0, 0x90, 0xF0],
-    [0xF0, 0x10, 0x20, 0x40, 0x40],
-    [0xF0, 0x90, 0xF0, 0x90, 0xF0],
-    [0xF0, 0x90, 0xF0, 0x10, 0xF0],
-    [0xF0, 0x90, 0xF0, 0x90, 0x90],
-    [0xE0, 0x90, 0xE0, 0x90, 0xE0],
-    [0xF0, 0x80, 0x80, 0x80, 0xF0],
-    [0xE0, 0x90, 0x90, 0x90, 0xE0],
-    [0xF0, 0x80, 0xF0, 0x80, 0xF0],
-    [0xF0, 0x80, 0xF0, 0x80, 0x80]
-    ];    
-    
-    /*
-    Memory: 4KiB
-    Registers: V0 to VF ....
-    Stack: 16 slots
-    framebuffer: 64x32
-    
-    */    
-    
-    /* 
-    Memory Layout:
-    |- 0x000 - 0x1FF: Chip 8 interpreter (contains font set in emulator)
-    |- 0x050 - 0x0A0: Used for the built in 4x5 pixel font set (0-F)
-    |- 0x200 - 0xFFF: Program ROM and work RAM
-    */        
-    
-    
-
-fn main() -> io::Result<()> {
-
-    let mut emulator = Emulator::new();
-    
     emulator.setup_noop();
-    
-    emulator = emulator.read_rom("/home/ersan/Downloads/test2.ch8")?;
-    
-    let (mut display, sdl_context) = Display::new();
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    // Octo sources are compiled on the fly and bring their own labels.
+    let mut labels = Symbols::new();
+    if rom.ends_with(".8o") {
+        let program = compile_octo(&rom);
+        emulator.load_rom(&program.rom)?;
+        labels = program.labels.iter().collect();
+    } else {
+        emulator = emulator.read_rom(&rom)?;
+    }
+    if let Some(path) = symbols {
+        labels = Symbols::load(&path).unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(2);
+        });
+    }
 
-    'running: loop {
-        // Emulator cycle
-        let byte = (emulator.memory[emulator.pc as usize] as u16)  << 8 | (emulator.memory[(emulator.pc + 1) as usize] as u16); 
-        println!("{:b}", byte);
-        
-        // This part may lead to some issues. (The subtract 4 part)
-        // But otherwise it would just give index out of bounds error.
-        if emulator.pc > emulator.memory.len() as u16 - 4 {
+    if let Some(path) = trace {
+        let mut tracer = Tracer::new(BufWriter::new(File::create(path)?));
+        for (start, end) in trace_ranges {
+            tracer = tracer.with_range(start, end);
+        }
+        emulator.set_tracer(Some(tracer));
+    }
+
+    if profile.is_some() || folded.is_some() {
+        emulator.set_profiler(Some(Profiler::new()));
+    }
+
+    let mut failed = false;
+    let result = if headless {
+        let summary = chip8::headless::run(&mut emulator, &mut scheduler, length, &keys);
+        failed = summary.error.is_some();
+        report_headless(&emulator, &summary, screen, dump, &view, &labels)
+    } else if debug {
+        let mut debugger = Debugger::new(scheduler);
+        debugger.set_symbols(labels.clone());
+        chip8::debugger::repl(&mut emulator, &mut debugger, io::stdin().lock(), io::stdout())
+    } else if let Some(port) = gdb_port {
+        let mut debugger = Debugger::new(scheduler);
+        eprintln!("waiting for gdb on 127.0.0.1:{}", port);
+        chip8::gdb::serve(&mut emulator, &mut debugger, port)
+    } else if let Some(glyphs) = terminal {
+        run_terminal(&mut emulator, scheduler, &glyphs, &view, &labels)
+    } else {
+        run(&mut emulator, scheduler, &rom, view, &labels)
+    };
+
+    // Profiles are written however the run ended.
+    if let Some(profiler) = emulator.profiler() {
+        if let Some(path) = profile {
+            std::fs::write(path, profiler.report(&emulator, &labels, 40))?;
+        }
+        if let Some(path) = folded {
+            std::fs::write(path, profiler.folded(&labels))?;
+        }
+    }
+    // Returning instead of exiting drops the emulator, which flushes the trace.
+    result?;
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+// Report how a headless run ended: errors on stderr, the screen to an image
+// and the machine state as JSON to a file or stdout.
+fn report_headless(emulator: &Emulator, summary: &Summary, screen: Option<String>, dump: Option<String>, view: &View, symbols: &Symbols) -> io::Result<()> {
+    if let Some(error) = &summary.error {
+        eprintln!("{}", error);
+        eprint!("{}", chip8::callstack::backtrace(emulator, symbols));
+    }
+    if let Some(path) = screen {
+        chip8::capture::save(path, emulator.display(), emulator.width(), emulator.height(), 1, &view.palette())?;
+    }
+    let state = chip8::headless::state_json(emulator, summary).to_string();
+    match dump {
+        Some(path) => std::fs::write(path, state + "\n"),
+        None => {
+            println!("{}", state);
+            Ok(())
+        },
+    }
+}
+
+// Window pixels per low resolution pixel, high resolution mode gets half.
+const DEFAULT_SCALE: usize = 10;
+
+// Names Glyphs::from_name knows for --terminal, the first is the default.
+const GLYPHS: [&str; 2] = ["half", "braille"];
+
+// How the frontends show the screen.
+struct View {
+    palette: usize,         // Index into Palette::NAMES
+    scale: usize,           // Window pixels per low resolution pixel
+    fullscreen: bool,
+    phosphor: Option<f32>,  // Persistence decay, None draws every frame as it is
+}
+
+impl View {
+    fn palette(&self) -> Palette {
+        Palette::from_name(Palette::NAMES[self.palette]).unwrap_or_default()
+    }
+}
+
+fn number_arg(flag: &str, value: Option<String>) -> u32 {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(number)) => number,
+        _ => {
+            eprintln!("{} expects a number", flag);
+            std::process::exit(2);
+        }
+    }
+}
+
+// `0x200-0x2FF`, an address range for --trace-range
+fn range_arg(flag: &str, value: Option<String>) -> (u16, u16) {
+    let address = |text: &str| parse_number(text).and_then(|address| u16::try_from(address).ok());
+    let range = value.as_deref().and_then(|value| {
+        let (start, end) = value.split_once('-').unwrap_or((value, value));
+        Some((address(start)?, address(end)?))
+    });
+    match range {
+        Some(range) => range,
+        None => {
+            eprintln!("{} expects an address range like 0x200-0x2FF", flag);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(not(feature = "terminal"))]
+fn run_terminal(_emulator: &mut Emulator, _scheduler: Scheduler, _glyphs: &str, _view: &View, _symbols: &Symbols) -> io::Result<()> {
+    eprintln!("chip8 was built without the `terminal` feature, rebuild with `--features terminal` to run in the terminal");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "terminal")]
+fn run_terminal(emulator: &mut Emulator, mut scheduler: Scheduler, glyphs: &str, view: &View, symbols: &Symbols) -> io::Result<()> {
+    let glyphs = Glyphs::from_name(glyphs).unwrap_or(Glyphs::HalfBlock);
+    let mut terminal = Terminal::new(glyphs, view.palette())?;
+    terminal.set_phosphor(view.phosphor.map(Phosphor::new));
+
+    loop {
+        match scheduler.run_frame(emulator) {
+            Ok(StepOutcome::Exited) => break,
+            Ok(_) => {},
+            Err(error) => {
+                // Back on the normal screen before printing.
+                drop(terminal);
+                eprintln!("{}", error);
+                eprint!("{}", backtrace(emulator, symbols));
+                return Ok(());
+            }
+        }
+        if !terminal.poll_keys(emulator)? {
             break;
         }
-        
-        //println!("{:?}    {}    {}  ", emulator.read_instruction(),  emulator.pc, byte);
-        
-        emulator.run_instruction(emulator.read_instruction());
-        emulator.timer_ticks();
-        
-        
-        for y in &emulator.display{
-            for x in y {
-                if !!x {print!("$");} else {print!(" ");}
+        terminal.draw_screen(emulator.display(), emulator.width(), emulator.height())?;
+
+        scheduler.wait_for_next_frame();
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(_emulator: &mut Emulator, _scheduler: Scheduler, _rom: &str, _view: View, _symbols: &Symbols) -> io::Result<()> {
+    eprintln!("chip8 was built without the `sdl` feature, rebuild with `--features sdl` to open a window");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "sdl")]
+fn run(emulator: &mut Emulator, mut scheduler: Scheduler, rom: &str, mut view: View, symbols: &Symbols) -> io::Result<()> {
+    let (mut display, sdl_context) = Display::new(view.scale, view.palette());
+    display.set_phosphor(view.phosphor.map(Phosphor::new));
+    if view.fullscreen {
+        if let Err(error) = display.set_fullscreen(true) {
+            eprintln!("fullscreen: {}", error);
+        }
+    }
+    // No audio device is no reason not to play, just without sound.
+    let mut audio = match Audio::new(&sdl_context) {
+        Ok(audio) => Some(audio),
+        Err(error) => {
+            eprintln!("audio: {}, running without sound", error);
+            None
+        },
+    };
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    // Hold backspace to rewind.
+    let mut rewind = Rewind::default();
+    let mut rewinding = false;
+
+    // F12 starts and stops recording a GIF.
+    let mut recorder: Option<Recorder> = None;
+
+    'running: loop {
+        // Emulator frame
+        if rewinding {
+            rewind.step_back(emulator);
+        } else {
+            match scheduler.run_frame(emulator) {
+                Ok(StepOutcome::Exited) => break,
+                Ok(_) => {},
+                Err(error) => {
+                    eprintln!("{}", error);
+                    eprint!("{}", backtrace(emulator, symbols));
+                    break;
+                }
             }
-            println!();
+            rewind.record(emulator);
         }
-        
-        
+        if let Some(audio) = audio.as_mut() {
+            audio.update(emulator);
+        }
+
         // Handle events
         for event in event_pump.poll_iter() {
             match event {
@@ -104,12 +477,76 @@ fn main() -> io::Result<()> {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                // F9 prints where the program is without stopping it.
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => eprint!("{}", backtrace(emulator, symbols)),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => screenshot(emulator, &display, rom),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    let fullscreen = !display.is_fullscreen();
+                    if let Err(error) = display.set_fullscreen(fullscreen) {
+                        eprintln!("fullscreen: {}", error);
+                    }
+                },
+                // Tab tries the next palette.
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    view.palette = (view.palette + 1) % Palette::NAMES.len();
+                    display.set_palette(view.palette());
+                    println!("palette {}", Palette::NAMES[view.palette]);
+                },
+                // Page up and down make the phosphor glow longer or shorter.
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::PageUp | Keycode::PageDown)),
+                    ..
+                } => {
+                    let step = if key == Keycode::PageUp { 0.1 } else { -0.1 };
+                    match display.phosphor_mut() {
+                        Some(phosphor) => {
+                            phosphor.set_decay(phosphor.decay() + step);
+                            println!("phosphor decay {:.1}", phosphor.decay());
+                        },
+                        None if step > 0.0 => {
+                            display.set_phosphor(Some(Phosphor::new(step)));
+                            println!("phosphor decay {:.1}", step);
+                        },
+                        None => {},
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => recorder = toggle_recording(recorder, &display, rom),
                 // Handle key presses
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
                     if let Some(key) = map_keys(key) {
                         emulator.key_down(key);
+                    } else if let Some((slot, save)) = save_slot(key) {
+                        use_save_slot(emulator, rom, slot, save);
+                        if !save {
+                            // The history no longer leads up to this state.
+                            rewind.clear();
+                        }
                     }
                 }
                 // Handle key releases
@@ -123,348 +560,72 @@ fn main() -> io::Result<()> {
                 _ => {}
             }
         }
-        if emulator.draw_flag {
-            display.draw_screen(&emulator.display);
+        display.draw_screen(emulator.display(), emulator.width(), emulator.height());
+        if let Some(recording) = recorder.as_mut() {
+            if let Err(error) = recording.frame(emulator.display(), emulator.width(), emulator.height(), display.palette()) {
+                eprintln!("recording stopped: {}", error);
+                recorder = None;
+            }
         }
-        
-        // adjust as needed
-        std::thread::sleep(std::time::Duration::from_micros(10000));
+
+        scheduler.wait_for_next_frame();
 
     }
 
+    // Quitting while recording keeps what was recorded.
+    if recorder.is_some() {
+        toggle_recording(recorder, &display, rom);
+    }
 
     Ok(())
 }
 
-
-
-pub struct Emulator {
-    pub memory: [u8; 4096],      // 4K memory; 0x000 - 0xFFF
-    v: [u8; 16],             // 16 8-bit registers; 0x0 - 0xF
-    i: u16,                         // Memory address register
-    pc: u16,                        // Program counter
-    stack: [u16; 16],       // Stack; 16 levels of 16-bit values
-    sp: u8,                         // Stack pointer; points to the top of the stack
-    delay_timer: u8,
-    sound_timer: u8,
-    display: [[bool; SCREEN_WIDTH];SCREEN_HEIGHT],
-    keys: [bool; 16],
-    draw_flag: bool,
-}    
-
-impl Emulator {
-
-    pub fn new() -> Emulator {
-        let mut emulator = Emulator {
-            memory: [0x0; 4096],
-            v: [0; 16],
-            i: 0x200,
-            pc: 0x200,
-            stack: [0; 16],
-            sp: 0,
-            delay_timer: 0,
-            sound_timer: 0,
-            display: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
-            keys: [false; 16],
-            draw_flag: false,
-        };    
-
-
-        emulator
-    }    
-
-
-    // This is my touch to chip8
-    pub fn setup_noop(&mut self){
-        for pos in (0..self.memory.len()).step_by(2){
-            self.memory[pos] = 0xF0;
-            self.memory[pos + 1] = 0x69;
-        }
+// F10, the screen as the window shows it.
+#[cfg(feature = "sdl")]
+fn screenshot(emulator: &Emulator, display: &Display, rom: &str) {
+    let path = capture::next_path(rom, "png");
+    let scale = display.scale(emulator.width(), emulator.height());
+    match capture::save(&path, emulator.display(), emulator.width(), emulator.height(), scale, display.palette()) {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(error) => eprintln!("{}: {}", path.display(), error),
     }
+}
 
-    pub fn key_down(&mut self, key: u8) {
-        self.keys[key as usize] = true;
+#[cfg(feature = "sdl")]
+fn toggle_recording(recorder: Option<Recorder>, display: &Display, rom: &str) -> Option<Recorder> {
+    if let Some(recorder) = recorder {
+        if let Err(error) = recorder.finish() {
+            eprintln!("recording: {}", error);
+        }
+        println!("recording stopped");
+        return None;
     }
-
-    pub fn key_up(&mut self, key: u8) {
-        self.keys[key as usize] = false;
+    let path = capture::next_path(rom, "gif");
+    let (width, height) = display.size();
+    match Recorder::create(&path, width, height, display.palette()) {
+        Ok(recorder) => {
+            println!("recording to {}", path.display());
+            Some(recorder)
+        },
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            None
+        },
     }
-    pub fn timer_ticks(&mut self) {
-        // Decrement delay timer if it's greater than zero every tick
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
+}
 
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP");
-            }
-            self.sound_timer -= 1;
-        }
+#[cfg(feature = "sdl")]
+fn use_save_slot(emulator: &mut Emulator, rom: &str, slot: u8, save: bool) {
+    let path = savestate::slot_path(rom, slot);
+    let result = if save {
+        std::fs::write(&path, emulator.save_state()).map_err(|error| error.to_string())
+    } else {
+        std::fs::read(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|state| emulator.load_state(&state).map_err(|error| error.to_string()))
+    };
+    match result {
+        Ok(()) => println!("{} slot {}", if save { "saved" } else { "loaded" }, slot),
+        Err(error) => eprintln!("slot {}: {}", slot, error),
     }
-
-    pub fn read_rom<P: std::convert::AsRef<std::path::Path>>(mut self, path: P) -> io::Result<Emulator> {
-        let file = File::open(path)?;
-        for (location, byte) in file.bytes().enumerate() {
-            self.memory[0x200 + location] = byte?;
-        }    
-        Ok(self)
-    }    
-    
-    fn read_instruction(&self) -> Option<Instruction> {
-        let opcode: OpCode = instruction::OpCode((self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize] as u16));
-        // 16 bit oku
-        Instruction::new(opcode)
-    }    
-
-    fn run_instruction(&mut self, instruction: Option<Instruction>) {
-        // println!("{}   {}   {}   {}   {}   {}   {}   {}   {}   {}   : {}  {:?}", self.v[0], self.v[1], self.v[2], self.v[3], self.v[4], self.v[5], self.v[6], self.v[7], self.v[13], self.v[14], self.pc, instruction);
-        self.draw_flag = false;
-
-        //self.display = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
-        self.pc = match instruction {
-            Some(Instruction::ClearDisplay) => {self.display = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT]; self.pc + 2}, //clear display
-            Some(Instruction::Return) => {
-                // Set the program counter to return position
-                self.sp -= 1;
-                self.stack[self.sp as usize] + 2
-            },
-            Some(Instruction::Jump(address)) => address,
-            Some(Instruction::Call(address)) => {
-                // go to an adress but to return.
-                self.stack[self.sp as usize] = self.pc as u16;
-                self.sp += 1;
-                address
-            },  
-            Some(Instruction::SkipIfEqualsByte(register, value)) => {
-                if self.v[register] == value {
-                    self.pc + 4
-                } else {
-                    self.pc + 2
-                }    
-            },    
-            Some(Instruction::SkipIfNotEqualsByte(register, value)) => {
-                if self.v[register] != value {
-                    self.pc + 4
-                } else {
-                    self.pc + 2
-                }    
-            },    
-            Some(Instruction::SkipIfEqual(regx, regy)) => {
-                if self.v[regx] == self.v[regy] {
-                    self.pc + 4
-                } else {
-                    self.pc + 2
-                }    
-            },    
-            Some(Instruction::LoadByte(register, value)) => {
-                self.v[register] = value;
-                self.pc + 2
-            },    
-            Some(Instruction::AddByte(register, value)) => {
-                self.v[register] = self.v[register] + value;
-                self.pc + 2
-            },    
-            Some(Instruction::Move(regx, regy)) => {
-                self.v[regx] = self.v[regy];
-                self.pc + 2
-            },    
-            Some(Instruction::Or(regx, regy)) => {
-                self.v[regx] = self.v[regx] | self.v[regy];
-                self.pc + 2
-            },    
-            Some(Instruction::And(regx, regy))=> {
-                self.v[regx] = self.v[regx] & self.v[regy];
-                self.pc + 2
-            },    
-            Some(Instruction::Xor(regx, regy)) => {
-                self.v[regx] = self.v[regx] ^ self.v[regy];
-                self.pc + 2
-            },    
-            Some(Instruction::Add(regx, regy)) => {
-                // Will probably change this line from converting into u16 at some point.
-                if self.v[regx] as u16 + self.v[regy] as u16 > 255 { self.v[0x0F] = 1 } else { self.v[0x0F] = 0 }
-                self.v[regx] += self.v[regy];
-                self.pc + 2
-            },    
-            Some(Instruction::Sub(regx, regy)) => {
-                if self.v[regx] > self.v[regy] { self.v[0x0F] = 1 } else { self.v[0x0F] = 0 }
-                self.v[regx] -= self.v[regy];
-                self.pc + 2
-            },    
-            Some(Instruction::ShiftRight(register)) => {
-                self.v[0x0F] = self.v[register] & 0x1;
-                self.v[register] >>= 1;
-                self.pc + 2
-            },    
-            Some(Instruction::ReverseSub(regx, regy)) => {
-                if self.v[regy] > self.v[regx] { self.v[0x0F] = 1 } else { self.v[0x0F] = 0 }
-                self.v[regx] = self.v[regy] - self.v[regx];
-                self.pc + 2
-            },    
-            Some(Instruction::ShiftLeft(register)) => {
-                self.v[0x0F] = self.v[register] & 128; // Most significant bit.
-                self.v[register] <<= 1;
-                self.pc + 2
-            },    
-            Some(Instruction::SkipIfNotEqual(regx, regy)) => {
-                if self.v[regx] != self.v[regy] {
-                    self.pc + 4
-                } else {
-                    self.pc + 2
-                }    
-            },    
-            Some(Instruction::LoadI(address)) => {
-                self.i = address;
-                self.pc + 2
-            },    
-            Some(Instruction::JumpPlusZero(addr)) => addr + (self.v[0] as u16),
-
-            Some(Instruction::Random(x, val)) => {
-                self.v[x] = val & rand::random::<u8>();
-                self.pc + 2
-            },    
-
-            Some(Instruction::Draw(regx, regy, value)) => {
-                let coordx = self.v[regx] as usize;
-                let coordy = self.v[regy] as usize;
-
-                let mut collision = false;
-                self.draw_flag = true;
-
-                // Display the rows and collumns of char
-                for row in 0..value {
-                    let pixels = self.memory[(self.i + row as u16) as usize];
-                    for col in 0..8 {
-                        if (pixels & (0x80 >> col)) != 0 {
-                            let x = (coordx + col) % SCREEN_WIDTH as usize;
-                            let y = (coordy + row as usize) % SCREEN_HEIGHT as usize;
-                            // to wrap around
-                            collision |= self.display[y][x];
-                            self.display[y][x] ^= true;
-                        }
-                    }
-                }
-                
-                self.v[0xF] = collision as u8;
-                self.pc + 2
-            },
-
-            Some(Instruction::SkipIfPressed(x)) => {
-                let key = self.v[x];
-                if self.keys[key as usize] {
-                    self.pc + 2
-                }else {
-                   self.pc
-                }
-            },
-
-            Some(Instruction::SkipIfNotPressed(x)) => {
-                let key = self.v[x];
-                if !self.keys[key as usize] {
-                    self.pc + 2
-                } else{
-                    self.pc
-                }
-            },
-
-            Some(Instruction::LoadDelayTimer(register)) => {
-                self.v[register] = self.delay_timer;
-                self.pc + 2
-            },
-            
-            Some(Instruction::WaitForKeyPress(register)) => {
-                let mut pressed = false;
-                for (i, &key) in self.keys.iter().enumerate() {
-                    if !!key {
-                        self.v[register] = i as u8;
-                        pressed = true;
-                        break;
-                    }
-                }
-                if pressed {
-                    self.pc + 2
-                }else {
-                    self.pc
-                }
-            },
-
-            Some(Instruction::SetDelayTimer(register)) => {
-                self.delay_timer = self.v[register];
-                self.pc + 2
-            },
-
-            Some(Instruction::SetSoundTimer(register)) => {
-                self.sound_timer = self.v[register];
-                self.pc + 2
-            },
-            
-            Some(Instruction::AddI(register)) => {
-                self.i += self.v[register] as u16;
-                self.pc + 2
-            },
-
-            Some(Instruction::LoadSprite(register)) => {
-                self.i = self.v[register] as u16 * 5;
-                self.pc + 2
-            },
-
-            Some(Instruction::StoreBCD(register)) => {
-                self.memory[self.i as usize] = self.v[register] / 100; // hundreds
-                self.memory[self.i as usize + 1] = (self.v[register] / 10) % 10; // tens
-                self.memory[self.i as usize + 2] = (self.v[register] % 100) % 10; // ones
-                self.pc + 2
-            },
-
-            Some(Instruction::StoreRegisters(register)) => {
-                for i in 0..=register {
-                    self.memory[self.i as usize + i] = self.v[i];
-                }
-                self.pc + 2
-            },
-
-            Some(Instruction::LoadRegisters(register)) => {
-                for i in 0..=register {
-                    self.v[i] = self.memory[self.i as usize + i];
-                }
-                self.pc + 2
-            },
-
-            Some(Instruction::NOOP) => {
-                self.pc + 2
-            },
-
-            None => {
-                eprintln!("Unsupported instruction: {:?}  {}", instruction, self.memory[self.pc as usize]);
-                self.pc
-            }
-        };    
-
-    }    
-}    
-
-// "/home/ersan/Downloads/octojam1title.ch8"
-
-
-
-fn map_keys(key: Keycode) -> Option<u8> {
-    match key {
-		Keycode::Num1 => Some(0x1),
-		Keycode::Num2 => Some(0x2),
-		Keycode::Num3 => Some(0x3),
-		Keycode::Num4 => Some(0xC),
-		Keycode::Q => Some(0x4),
-		Keycode::W => Some(0x5),
-		Keycode::E => Some(0x6),
-		Keycode::R => Some(0xD),
-		Keycode::A => Some(0x7),
-		Keycode::S => Some(0x8),
-		Keycode::D => Some(0x9),
-		Keycode::F => Some(0xE),
-		Keycode::Z => Some(0xA),
-		Keycode::X => Some(0x0),
-		Keycode::C => Some(0xB),
-		Keycode::V => Some(0xF),
-		_ => None,
-	}
-}
\ No newline at end of file
+}
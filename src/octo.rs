@@ -0,0 +1,1022 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::asm::{parse_number, AsmError};
+use crate::disasm::ORIGIN;
+use crate::instruction::{Address, Instruction, Register};
+
+/*
+Compiler for Octo, the high level assembly most XO-CHIP and SCHIP homebrew is
+written in. Supported:
+
+    : name                  label, `:next name` labels the next instruction's operand
+    :const NAME value       :calc NAME { expression }
+    :alias name vx          :macro name args { body }
+    :org addr               :byte value     :pointer addr
+    :unpack n label         :unpack long label
+    loop ... again          while condition
+    if condition then statement
+    if condition begin ... else ... end
+
+Bare numbers emit bytes, so sprites are written as lists of 0b/0x literals, and
+a bare label name calls it. Like Octo, a jump to `main` is put at 0x200 unless
+the program starts with `: main`.
+*/
+
+// Guards against macros that expand into themselves.
+const MAX_EXPANSIONS: usize = 100_000;
+
+// A compiled program: the ROM and where every label ended up.
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, Address>,
+}
+
+impl Program {
+
+    // One `0x202 main` line per label, sorted by address.
+    pub fn label_table(&self) -> String {
+        let mut labels: Vec<(&String, &Address)> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, &address)| (address, name));
+        let mut out = String::new();
+        for (name, address) in labels {
+            let _ = writeln!(out, "0x{:03X} {}", address, name);
+        }
+        out
+    }
+}
+
+pub fn compile(source: &str) -> Result<Program, AsmError> {
+    Compiler::new("<input>", source).compile()
+}
+
+pub fn compile_file<P: AsRef<Path>>(path: P) -> Result<Program, AsmError> {
+    let path = path.as_ref();
+    let file = path.display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|error| AsmError {
+        file: file.clone(),
+        line: 0,
+        column: 0,
+        message: error.to_string(),
+    })?;
+    Compiler::new(&file, &source).compile()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+// Split on whitespace, `#` starts a comment.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (column, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+            match (c.is_whitespace(), start) {
+                (true, Some(begin)) => {
+                    tokens.push(Token { text: line[begin..column].to_string(), line: index + 1, column: begin + 1 });
+                    start = None;
+                },
+                (false, None) => start = Some(column),
+                _ => {},
+            }
+        }
+    }
+    tokens
+}
+
+// The comparisons `if` and `while` understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison {
+    fn from_token(text: &str) -> Option<Comparison> {
+        Some(match text {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            _ => return None,
+        })
+    }
+
+    fn negate(self) -> Comparison {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterEqual,
+            Comparison::GreaterEqual => Comparison::Less,
+            Comparison::Greater => Comparison::LessEqual,
+            Comparison::LessEqual => Comparison::Greater,
+            Comparison::Key => Comparison::NotKey,
+            Comparison::NotKey => Comparison::Key,
+        }
+    }
+}
+
+// Right hand side of a comparison or assignment.
+enum Operand {
+    Register(Register),
+    Byte(u8),
+}
+
+// Places that wait for a label to be defined.
+enum Fixup {
+    // Low 12 bits of the instruction at this address.
+    Address12(Address),
+    // A whole 16 bit word.
+    Address16(Address),
+    // The two `vN := byte` of :unpack. The high nibble of the first one is
+    // given, unless it is `:unpack long` and takes the whole high byte.
+    Unpack(Address, Option<u8>),
+}
+
+enum Control {
+    // Address of the jump that skips the block when the condition fails.
+    If { jump: Address, else_seen: bool, at: Token },
+    Loop { start: Address, breaks: Vec<Address>, at: Token },
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    file: String,
+    // Remaining tokens, reversed so the next one is popped off the end.
+    tokens: Vec<Token>,
+    last: Option<Token>,
+    rom: Vec<u8>,
+    here: Address,
+    labels: HashMap<String, Address>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, Register>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(String, Fixup, Token)>,
+    control: Vec<Control>,
+    expansions: usize,
+}
+
+impl Compiler {
+
+    fn new(file: &str, source: &str) -> Compiler {
+        let mut tokens = tokenize(source);
+        tokens.reverse();
+        Compiler {
+            file: file.to_string(),
+            tokens,
+            last: None,
+            rom: Vec::new(),
+            here: ORIGIN,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            control: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn compile(mut self) -> Result<Program, AsmError> {
+        // Leave room for `jump main` unless main comes first anyway.
+        let main_first = matches!(self.tokens.as_slice(), [.., second, first] if first.text == ":" && second.text == "main");
+        if !main_first {
+            self.emit(Instruction::Jump(0));
+        }
+
+        while let Some(token) = self.next_token() {
+            self.statement(token)?;
+        }
+
+        if let Some(control) = self.control.last() {
+            let (Control::If { at, .. } | Control::Loop { at, .. }) = control;
+            return Err(self.error(at, format!("`{}` is never closed", at.text)));
+        }
+
+        if !main_first {
+            match self.labels.get("main") {
+                Some(&main) => self.patch_jump(ORIGIN, main),
+                None => return Err(AsmError {
+                    file: self.file.clone(),
+                    line: 0,
+                    column: 0,
+                    message: "the program has no `main` label".to_string(),
+                }),
+            }
+        }
+        for (name, fixup, token) in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&name) {
+                Some(&target) => target,
+                None => return Err(self.error(&token, format!("undefined label `{}`", name))),
+            };
+            self.apply(fixup, target, &token)?;
+        }
+
+        let labels = self.labels.into_iter().collect();
+        Ok(Program { rom: self.rom, labels })
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.pop();
+        if token.is_some() {
+            self.last = token.clone();
+        }
+        token
+    }
+
+    // The next token, or an error pointing at the last one.
+    fn expect(&mut self, what: &str) -> Result<Token, AsmError> {
+        match self.next_token() {
+            Some(token) => Ok(token),
+            None => {
+                let at = self.last.clone().unwrap_or(Token { text: String::new(), line: 0, column: 0 });
+                Err(self.error(&at, format!("expected {} before the end of the program", what)))
+            },
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name("a label name")?;
+                self.define_label(&name, self.here)?;
+            },
+            ":next" => {
+                let name = self.name("a label name")?;
+                self.define_label(&name, self.here.wrapping_add(1))?;
+            },
+            ":const" => {
+                let name = self.name("a constant name")?;
+                let value = self.expect("a value")?;
+                let value = self.value(&value)?;
+                self.constants.insert(name.text, value as f64);
+            },
+            ":calc" => {
+                let name = self.name("a constant name")?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            },
+            ":alias" => {
+                let name = self.name("an alias name")?;
+                let register = self.expect("a register")?;
+                let register = self.register(&register)?;
+                self.aliases.insert(name.text, register);
+            },
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address = self.number_or_calc()?;
+                if !(ORIGIN as i64..=0xFFFF).contains(&address) {
+                    return Err(self.error(&token, format!("0x{:X} is outside of the program", address)));
+                }
+                self.here = address as Address;
+            },
+            ":byte" => {
+                let value = self.number_or_calc()?;
+                self.emit_byte(value as u8);
+            },
+            ":pointer" => {
+                let target = self.expect("an address")?;
+                let address = self.here;
+                self.emit_word(0);
+                self.address(&target, Fixup::Address16(address))?;
+            },
+            ":call" => {
+                let target = self.expect("an address")?;
+                self.emit_fixed(Instruction::Call(0), &target)?;
+            },
+            ":unpack" => {
+                let nibble = self.expect("a nibble or `long`")?;
+                let nibble = if nibble.text == "long" { None } else { Some(self.value(&nibble)? as u8 & 0x0F) };
+                let target = self.expect("a label")?;
+                let address = self.here;
+                self.emit(Instruction::LoadByte(0, 0));
+                self.emit(Instruction::LoadByte(1, 0));
+                self.address(&target, Fixup::Unpack(address, nibble))?;
+            },
+            // Octo's own debugger hooks, they mean nothing in a ROM.
+            ":proto" | ":breakpoint" => {
+                self.expect("a name")?;
+            },
+            ":monitor" => {
+                self.expect("a value")?;
+                self.expect("a format")?;
+            },
+
+            "loop" => self.control.push(Control::Loop { start: self.here, breaks: Vec::new(), at: token }),
+            "while" => {
+                let comparison = self.condition()?;
+                self.emit_skip(comparison)?;
+                let jump = self.here;
+                self.emit(Instruction::Jump(0));
+                match self.control.iter_mut().rev().find(|control| matches!(control, Control::Loop { .. })) {
+                    Some(Control::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err(self.error(&token, "`while` outside of a loop")),
+                }
+            },
+            "again" => match self.control.pop() {
+                Some(Control::Loop { start, breaks, .. }) => {
+                    self.emit(Instruction::Jump(start));
+                    for jump in breaks {
+                        self.patch_jump(jump, self.here);
+                    }
+                },
+                _ => return Err(self.error(&token, "`again` without a `loop`")),
+            },
+            "if" => {
+                let comparison = self.condition()?;
+                let block = self.expect("`then` or `begin`")?;
+                match block.text.as_str() {
+                    // Skip the next statement when the condition fails.
+                    "then" => self.emit_skip((comparison.0, comparison.1.negate(), comparison.2))?,
+                    // Skip the jump past the block when it holds.
+                    "begin" => {
+                        self.emit_skip(comparison)?;
+                        let jump = self.here;
+                        self.emit(Instruction::Jump(0));
+                        self.control.push(Control::If { jump, else_seen: false, at: block });
+                    },
+                    _ => return Err(self.error(&block, format!("expected `then` or `begin`, found `{}`", block.text))),
+                }
+            },
+            "else" => match self.control.last() {
+                Some(Control::If { jump, else_seen: false, .. }) => {
+                    let skip_else = self.here;
+                    let jump = *jump;
+                    self.emit(Instruction::Jump(0));
+                    self.patch_jump(jump, self.here);
+                    self.control.pop();
+                    self.control.push(Control::If { jump: skip_else, else_seen: true, at: token });
+                },
+                _ => return Err(self.error(&token, "`else` without a `begin`")),
+            },
+            "end" => match self.control.pop() {
+                Some(Control::If { jump, .. }) => self.patch_jump(jump, self.here),
+                _ => return Err(self.error(&token, "`end` without a `begin`")),
+            },
+
+            "clear" => self.emit(Instruction::ClearDisplay),
+            "return" | ";" => self.emit(Instruction::Return),
+            "exit" => self.emit(Instruction::Exit),
+            "lores" => self.emit(Instruction::LowRes),
+            "hires" => self.emit(Instruction::HighRes),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "audio" => self.emit(Instruction::LoadAudio),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollDown(n));
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollUp(n));
+            },
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::SelectPlanes(n));
+            },
+            "bcd" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::StoreBCD(x));
+            },
+            "saveflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::StoreFlags(x));
+            },
+            "loadflags" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::LoadFlags(x));
+            },
+            "save" | "load" => {
+                let x = self.next_register()?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next_token();
+                    let y = self.next_register()?;
+                    if token.text == "save" { Instruction::SaveRange(x, y) } else { Instruction::LoadRange(x, y) }
+                } else if token.text == "save" {
+                    Instruction::StoreRegisters(x)
+                } else {
+                    Instruction::LoadRegisters(x)
+                };
+                self.emit(instruction);
+            },
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.nibble()?;
+                self.emit(Instruction::Draw(x, y, n));
+            },
+            "jump" => {
+                let target = self.expect("an address")?;
+                self.emit_fixed(Instruction::Jump(0), &target)?;
+            },
+            "jump0" => {
+                let target = self.expect("an address")?;
+                self.emit_fixed(Instruction::JumpPlusZero(0), &target)?;
+            },
+            "native" => {
+                let target = self.expect("an address")?;
+                let address = self.value(&target)?;
+                self.emit_word(address as u16 & 0x0FFF);
+            },
+            "i" => self.index_statement()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.operator(":=")?;
+                let x = self.next_register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => Instruction::SetDelayTimer(x),
+                    "buzzer" => Instruction::SetSoundTimer(x),
+                    _ => Instruction::SetPitch(x),
+                });
+            },
+
+            _ if self.is_register(&token.text) => self.register_statement(&token)?,
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            _ if parse_number(token.text.trim_start_matches('-')).is_some() || self.constants.contains_key(&token.text) => {
+                let value = self.value(&token)?;
+                if !(-128..=255).contains(&value) {
+                    return Err(self.error(&token, format!("{} does not fit in a byte", value)));
+                }
+                self.emit_byte(value as u8);
+            },
+            _ if token.text.starts_with(':') => return Err(self.error(&token, format!("unknown directive `{}`", token.text))),
+            // Anything else is a subroutine, maybe one defined further down.
+            _ => {
+                self.check_name(&token)?;
+                self.emit_fixed(Instruction::Call(0), &token)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AsmError> {
+        let operator = self.expect("`:=` or `+=`")?;
+        match operator.text.as_str() {
+            ":=" => {
+                let value = self.expect("an address")?;
+                match value.text.as_str() {
+                    "hex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::LoadSprite(x));
+                    },
+                    "bighex" => {
+                        let x = self.next_register()?;
+                        self.emit(Instruction::LoadBigSprite(x));
+                    },
+                    "long" => {
+                        let target = self.expect("an address")?;
+                        self.emit(Instruction::LoadILong(0));
+                        self.address(&target, Fixup::Address16(self.here - 2))?;
+                    },
+                    _ => self.emit_fixed(Instruction::LoadI(0), &value)?,
+                }
+            },
+            "+=" => {
+                let x = self.next_register()?;
+                self.emit(Instruction::AddI(x));
+            },
+            _ => return Err(self.error(&operator, format!("expected `:=` or `+=`, found `{}`", operator.text))),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let x = self.register(token)?;
+        let operator = self.expect("an operator")?;
+        let value = self.expect("a value")?;
+
+        let instruction = match (operator.text.as_str(), value.text.as_str()) {
+            (":=", "delay") => Instruction::LoadDelayTimer(x),
+            (":=", "key") => Instruction::WaitForKeyPress(x),
+            (":=", "random") => {
+                let mask = self.expect("a mask")?;
+                Instruction::Random(x, self.byte(&mask)?)
+            },
+            (":=", _) => match self.operand(&value)? {
+                Operand::Register(y) => Instruction::Move(x, y),
+                Operand::Byte(byte) => Instruction::LoadByte(x, byte),
+            },
+            ("+=", _) => match self.operand(&value)? {
+                Operand::Register(y) => Instruction::Add(x, y),
+                Operand::Byte(byte) => Instruction::AddByte(x, byte),
+            },
+            ("-=", _) => match self.operand(&value)? {
+                Operand::Register(y) => Instruction::Sub(x, y),
+                Operand::Byte(byte) => Instruction::AddByte(x, byte.wrapping_neg()),
+            },
+            ("=-", _) => Instruction::ReverseSub(x, self.register(&value)?),
+            ("|=", _) => Instruction::Or(x, self.register(&value)?),
+            ("&=", _) => Instruction::And(x, self.register(&value)?),
+            ("^=", _) => Instruction::Xor(x, self.register(&value)?),
+            (">>=", _) => Instruction::ShiftRight(x, self.register(&value)?),
+            ("<<=", _) => Instruction::ShiftLeft(x, self.register(&value)?),
+            _ => return Err(self.error(&operator, format!("unknown operator `{}`", operator.text))),
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    // `vx op value` of an if or while.
+    fn condition(&mut self) -> Result<(Register, Comparison, Option<Operand>), AsmError> {
+        let x = self.next_register()?;
+        let operator = self.expect("a comparison")?;
+        let comparison = Comparison::from_token(&operator.text)
+            .ok_or_else(|| self.error(&operator, format!("unknown comparison `{}`", operator.text)))?;
+        if matches!(comparison, Comparison::Key | Comparison::NotKey) {
+            return Ok((x, comparison, None));
+        }
+        let value = self.expect("a value")?;
+        Ok((x, comparison, Some(self.operand(&value)?)))
+    }
+
+    // Emit code that skips the next instruction when the comparison holds.
+    fn emit_skip(&mut self, (x, comparison, value): (Register, Comparison, Option<Operand>)) -> Result<(), AsmError> {
+        const VF: Register = 0xF;
+        let instruction = match (comparison, value) {
+            (Comparison::Key, _) => Instruction::SkipIfPressed(x),
+            (Comparison::NotKey, _) => Instruction::SkipIfNotPressed(x),
+            (Comparison::Equal, Some(Operand::Register(y))) => Instruction::SkipIfEqual(x, y),
+            (Comparison::Equal, Some(Operand::Byte(byte))) => Instruction::SkipIfEqualsByte(x, byte),
+            (Comparison::NotEqual, Some(Operand::Register(y))) => Instruction::SkipIfNotEqual(x, y),
+            (Comparison::NotEqual, Some(Operand::Byte(byte))) => Instruction::SkipIfNotEqualsByte(x, byte),
+            // The rest subtract into VF and test the borrow flag, which is
+            // set when the left side is at least the right side.
+            (comparison, Some(value)) => {
+                let flag_means_x_at_least = matches!(comparison, Comparison::Less | Comparison::GreaterEqual);
+                if flag_means_x_at_least {
+                    // VF := x - value
+                    match value {
+                        Operand::Register(y) => {
+                            self.emit(Instruction::Move(VF, x));
+                            self.emit(Instruction::Sub(VF, y));
+                        },
+                        Operand::Byte(byte) => {
+                            self.emit(Instruction::LoadByte(VF, byte));
+                            self.emit(Instruction::ReverseSub(VF, x));
+                        },
+                    }
+                } else {
+                    // VF := value - x
+                    match value {
+                        Operand::Register(y) => self.emit(Instruction::Move(VF, y)),
+                        Operand::Byte(byte) => self.emit(Instruction::LoadByte(VF, byte)),
+                    }
+                    self.emit(Instruction::Sub(VF, x));
+                }
+                let holds_when = match comparison {
+                    Comparison::Less | Comparison::Greater => 0,
+                    _ => 1,
+                };
+                Instruction::SkipIfEqualsByte(VF, holds_when)
+            },
+            (_, None) => unreachable!("only key comparisons have no value"),
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.name("a macro name")?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.expect("`{`")?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+        let body = self.braced()?;
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(token, "too many macro expansions, does a macro call itself?"));
+        }
+        let argument_count = self.macros[&token.text].args.len();
+        let mut values = HashMap::new();
+        for index in 0..argument_count {
+            let value = self.expect("a macro argument")?;
+            values.insert(self.macros[&token.text].args[index].clone(), value.text);
+        }
+        let body = &self.macros[&token.text].body;
+        let expanded: Vec<Token> = body.iter().rev().map(|body_token| Token {
+            text: values.get(&body_token.text).cloned().unwrap_or_else(|| body_token.text.clone()),
+            // Errors inside a macro point at where it was used.
+            line: token.line,
+            column: token.column,
+        }).collect();
+        self.tokens.extend(expanded);
+        Ok(())
+    }
+
+    // Tokens up to the matching `}`, the `{` already taken.
+    fn braced(&mut self) -> Result<Vec<Token>, AsmError> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.expect("`}`")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+    }
+
+    fn number_or_calc(&mut self) -> Result<i64, AsmError> {
+        if self.peek() == Some("{") {
+            return Ok(self.calc()?.floor() as i64);
+        }
+        let token = self.expect("a value")?;
+        self.value(&token)
+    }
+
+    // `{ expression }`
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let open = self.expect("`{`")?;
+        if open.text != "{" {
+            return Err(self.error(&open, format!("expected `{{`, found `{}`", open.text)));
+        }
+        let mut tokens = self.braced()?;
+        tokens.reverse();
+        let value = self.expression(&mut tokens, &open)?;
+        if let Some(extra) = tokens.last() {
+            return Err(self.error(extra, format!("unexpected `{}`", extra.text)));
+        }
+        Ok(value)
+    }
+
+    // Octo evaluates right to left without precedence: `2 * 3 + 1` is 8.
+    fn expression(&self, tokens: &mut Vec<Token>, open: &Token) -> Result<f64, AsmError> {
+        let token = tokens.pop().ok_or_else(|| self.error(open, "expected a value"))?;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| (a == 0.0) as i64 as f64),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return Ok(function(self.expression(tokens, open)?));
+        }
+        if token.text == "@" {
+            let address = self.expression(tokens, open)? as usize;
+            let offset = address.wrapping_sub(ORIGIN as usize);
+            return Ok(self.rom.get(offset).copied().unwrap_or(0) as f64);
+        }
+
+        let left = match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, open)?;
+                match tokens.pop() {
+                    Some(close) if close.text == ")" => value,
+                    _ => return Err(self.error(&token, "`(` is never closed")),
+                }
+            },
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => self.value(&token)? as f64,
+        };
+        let operator = match tokens.last() {
+            Some(operator) if operator.text != ")" => tokens.pop().unwrap(),
+            _ => return Ok(left),
+        };
+        let right = self.expression(tokens, open)?;
+        let (a, b) = (left as i64, right as i64);
+        Ok(match operator.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.wrapping_shl(b as u32) as f64,
+            ">>" => a.wrapping_shr(b as u32) as f64,
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(self.error(&operator, format!("unknown operator `{}`", operator.text))),
+        })
+    }
+
+    // A number, constant or already defined label.
+    fn value(&self, token: &Token) -> Result<i64, AsmError> {
+        let text = token.text.as_str();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        if let Some(value) = parse_number(digits) {
+            return Ok(if negative { -value } else { value });
+        }
+        if let Some(&value) = self.constants.get(text) {
+            return Ok(value.floor() as i64);
+        }
+        if let Some(&address) = self.labels.get(text) {
+            return Ok(address as i64);
+        }
+        Err(self.error(token, format!("unknown value `{}`", text)))
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        let value = self.value(token)?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(token, format!("{} does not fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        let token = self.expect("a number")?;
+        let value = self.value(&token)?;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(&token, format!("{} does not fit in a nibble", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn operand(&self, token: &Token) -> Result<Operand, AsmError> {
+        if self.is_register(&token.text) {
+            Ok(Operand::Register(self.register(token)?))
+        } else {
+            Ok(Operand::Byte(self.byte(token)?))
+        }
+    }
+
+    fn operator(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.expect(expected)?;
+        if token.text != expected {
+            return Err(self.error(&token, format!("expected `{}`, found `{}`", expected, token.text)));
+        }
+        Ok(())
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        self.aliases.contains_key(text) || register_number(text).is_some()
+    }
+
+    fn register(&self, token: &Token) -> Result<Register, AsmError> {
+        self.aliases.get(&token.text).copied()
+            .or_else(|| register_number(&token.text))
+            .ok_or_else(|| self.error(token, format!("expected a register, found `{}`", token.text)))
+    }
+
+    fn next_register(&mut self) -> Result<Register, AsmError> {
+        let token = self.expect("a register")?;
+        self.register(&token)
+    }
+
+    fn name(&mut self, what: &str) -> Result<Token, AsmError> {
+        let token = self.expect(what)?;
+        self.check_name(&token)?;
+        Ok(token)
+    }
+
+    // Names must not look like numbers or registers.
+    fn check_name(&self, token: &Token) -> Result<(), AsmError> {
+        let text = token.text.as_str();
+        let valid = text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            && !text.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+            && register_number(text).is_none();
+        if valid {
+            Ok(())
+        } else {
+            Err(self.error(token, format!("`{}` is not a valid name", text)))
+        }
+    }
+
+    fn define_label(&mut self, name: &Token, address: Address) -> Result<(), AsmError> {
+        if self.labels.insert(name.text.clone(), address).is_some() {
+            return Err(self.error(name, format!("`{}` is defined twice", name.text)));
+        }
+        Ok(())
+    }
+
+    // Emit an instruction whose 12 bit address may be a forward reference.
+    fn emit_fixed(&mut self, instruction: Instruction, target: &Token) -> Result<(), AsmError> {
+        let address = self.here;
+        self.emit(instruction);
+        self.address(target, Fixup::Address12(address))
+    }
+
+    // Resolve `target` now if possible, otherwise once it is defined.
+    fn address(&mut self, target: &Token, fixup: Fixup) -> Result<(), AsmError> {
+        let known = parse_number(&target.text).is_some()
+            || self.constants.contains_key(&target.text)
+            || self.labels.contains_key(&target.text);
+        if known {
+            let value = self.value(target)?;
+            self.apply(fixup, value as Address, target)
+        } else {
+            self.check_name(target)?;
+            self.fixups.push((target.text.clone(), fixup, target.clone()));
+            Ok(())
+        }
+    }
+
+    fn apply(&mut self, fixup: Fixup, target: Address, token: &Token) -> Result<(), AsmError> {
+        match fixup {
+            Fixup::Address12(at) => {
+                if target > 0xFFF {
+                    return Err(self.error(token, format!("`{}` at 0x{:04X} is out of reach, use `i := long`", token.text, target)));
+                }
+                let word = self.word_at(at) & 0xF000 | target;
+                self.put_word(at, word);
+            },
+            Fixup::Address16(at) => self.put_word(at, target),
+            Fixup::Unpack(at, nibble) => {
+                let high = match nibble {
+                    Some(nibble) => nibble << 4 | (target >> 8) as u8 & 0x0F,
+                    None => (target >> 8) as u8,
+                };
+                self.put_byte(at + 1, high);
+                self.put_byte(at + 3, target as u8);
+            },
+        }
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, at: Address, target: Address) {
+        self.put_word(at, Instruction::Jump(target).encode().0);
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.emit_word(instruction.encode().0);
+        if let Instruction::LoadILong(address) = instruction {
+            self.emit_word(address);
+        }
+    }
+
+    fn emit_word(&mut self, word: u16) {
+        for byte in word.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        self.put_byte(self.here, byte);
+        self.here = self.here.wrapping_add(1);
+    }
+
+    fn put_byte(&mut self, address: Address, byte: u8) {
+        let offset = address.wrapping_sub(ORIGIN) as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+    }
+
+    fn put_word(&mut self, address: Address, word: u16) {
+        let [high, low] = word.to_be_bytes();
+        self.put_byte(address, high);
+        self.put_byte(address.wrapping_add(1), low);
+    }
+
+    fn word_at(&self, address: Address) -> u16 {
+        let offset = address.wrapping_sub(ORIGIN) as usize;
+        u16::from_be_bytes([self.rom[offset], self.rom[offset + 1]])
+    }
+}
+
+fn register_number(text: &str) -> Option<Register> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(digit, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source).unwrap().rom
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        let source = ": main
+            :calc A { 2 * 3 + 1 }
+            :calc B { 10 - 2 - 3 }
+            :calc C { ( 1 + 2 ) * 3 }
+            :calc D { A + 1 }
+            A B C D";
+        assert_eq!(rom(source), [8, 11, 9, 9]);
+    }
+
+    #[test]
+    fn loop_and_while() {
+        let source = ": main
+            loop
+                v0 += 1
+                while v0 != 5
+            again";
+        // ADD V0, 1; SNE V0, 5; JP past the loop; JP back to the start
+        assert_eq!(rom(source), [0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn if_then() {
+        // SNE V0, 3 skips the statement when the condition fails.
+        assert_eq!(rom(": main if v0 == 3 then v1 := 2"), [0x40, 0x03, 0x61, 0x02]);
+        assert_eq!(rom(": main if v0 key then clear"), [0xE0, 0xA1, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = ": main
+            if v0 > v1 begin
+                v2 := 1
+            else
+                v2 := 2
+            end";
+        assert_eq!(rom(source), [
+            0x8F, 0x10, 0x8F, 0x05,     // VF := v1 - v0, the flag is clear when v0 > v1
+            0x3F, 0x00,                 // skip the jump to else when it holds
+            0x12, 0x0C,
+            0x62, 0x01,
+            0x12, 0x0E,                 // jump past else
+            0x62, 0x02,
+        ]);
+    }
+
+    #[test]
+    fn unpack() {
+        let source = ": main
+            :unpack 0xA data
+            :unpack long data
+            :unpack 1 main
+            : data 0x12";
+        assert_eq!(rom(source), [
+            0x60, 0xA2, 0x61, 0x0C,
+            0x60, 0x02, 0x61, 0x0C,
+            0x60, 0x12, 0x61, 0x00,
+            0x12,
+        ]);
+    }
+
+    #[test]
+    fn jump_to_main_unless_it_comes_first() {
+        assert_eq!(rom("0x55 : main clear"), [0x12, 0x03, 0x55, 0x00, 0xE0]);
+    }
+}
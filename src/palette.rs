@@ -0,0 +1,46 @@
+// Colours for the framebuffer. Each pixel holds one bit per XO-CHIP plane,
+// so a pixel value 0-3 indexes straight into the palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    pub const fn new(colors: [[u8; 3]; 4]) -> Palette {
+        Palette { colors }
+    }
+
+    pub fn color(&self, pixel: u8) -> [u8; 3] {
+        self.colors[(pixel & 0x3) as usize]
+    }
+
+    // Every name from_name knows, in the order the frontends cycle through them.
+    pub const NAMES: [&'static str; 6] = ["default", "mono", "octo", "amber", "lcd", "colorblind"];
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "default" => return Some(Palette::default()),
+            // White on black, the second plane in greys.
+            "mono" => [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0x80, 0x80, 0x80], [0xC0, 0xC0, 0xC0]],
+            // Octo's own colours, most XO-CHIP games are drawn for these.
+            "octo" => [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]],
+            "amber" => [[0x1A, 0x10, 0x00], [0xFF, 0xB0, 0x00], [0x99, 0x60, 0x00], [0xFF, 0xE0, 0x80]],
+            "lcd" => [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]],
+            // Okabe-Ito colours, told apart with any kind of colour blindness.
+            "colorblind" => [[0x00, 0x00, 0x00], [0x56, 0xB4, 0xE9], [0xE6, 0x9F, 0x00], [0xF0, 0xE4, 0x42]],
+            _ => return None,
+        };
+        Some(Palette::new(colors))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new([
+            [0x00, 0x00, 0x00], // off
+            [0x34, 0xE6, 0x2F], // plane 1
+            [0xE6, 0x8A, 0x2F], // plane 2
+            [0xF5, 0xF5, 0xE6], // both planes
+        ])
+    }
+}
@@ -0,0 +1,105 @@
+use crate::emulator::Screen;
+use crate::palette::Palette;
+
+/*
+Simulates the VIP's CRT phosphor to hide XOR flicker. A pixel that turns on
+lights up at once, a pixel that turns off fades towards its new colour,
+keeping `decay` of the difference every frame. Sprites that are erased and
+redrawn within a frame or two then barely dim instead of blinking.
+
+0.0 is no persistence at all, 0.5 fades out in about five frames and 0.8
+in about twenty.
+*/
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+pub struct Phosphor {
+    decay: f32,
+    glow: Vec<[f32; 3]>,        // What each pixel shows, width x height
+    width: usize,
+    height: usize,
+}
+
+impl Phosphor {
+
+    pub fn new(decay: f32) -> Phosphor {
+        Phosphor { decay: decay.clamp(0.0, 0.99), glow: Vec::new(), width: 0, height: 0 }
+    }
+
+    pub fn decay(&self) -> f32 {
+        self.decay
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.clamp(0.0, 0.99);
+    }
+
+    // Advance one frame and return the colour of every pixel of the top
+    // left width x height, row by row. Call once per frame shown.
+    pub fn frame(&mut self, screen: &Screen, width: usize, height: usize, palette: &Palette) -> Vec<[u8; 3]> {
+        // A resolution change clears the screen anyway, start over.
+        if (width, height) != (self.width, self.height) {
+            self.glow = vec![[0.0; 3]; width * height];
+            self.width = width;
+            self.height = height;
+        }
+        let mut colors = Vec::with_capacity(width * height);
+        for (y, row) in screen.iter().take(height).enumerate() {
+            for (x, &pixel) in row.iter().take(width).enumerate() {
+                let target = palette.color(pixel);
+                let glow = &mut self.glow[y * width + x];
+                for (channel, &value) in glow.iter_mut().zip(&target) {
+                    *channel = if pixel != 0 {
+                        value as f32
+                    } else {
+                        value as f32 + (*channel - value as f32) * self.decay
+                    };
+                }
+                colors.push(glow.map(|channel| channel.round() as u8));
+            }
+        }
+        colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{HIRES_HEIGHT, HIRES_WIDTH};
+
+    const GREY: Palette = Palette::new([[0, 0, 0], [200, 200, 200], [0, 0, 0], [0, 0, 0]]);
+
+    // The red channel of pixel (1, 0) on a 4x2 screen, frame after frame.
+    fn fade(phosphor: &mut Phosphor, screen: &Screen, frames: usize) -> Vec<u8> {
+        (0..frames).map(|_| phosphor.frame(screen, 4, 2, &GREY)[1][0]).collect()
+    }
+
+    #[test]
+    fn lit_pixels_fade_out_and_come_back_at_once() {
+        let mut screen = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        screen[0][1] = 1;
+        let dark = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+
+        // Half the difference is left every frame, so 200 is gone after 9.
+        let mut phosphor = Phosphor::new(0.5);
+        assert_eq!(fade(&mut phosphor, &screen, 2), [200, 200]);
+        assert_eq!(fade(&mut phosphor, &dark, 9), [100, 50, 25, 13, 6, 3, 2, 1, 0]);
+        assert_eq!(fade(&mut phosphor, &screen, 1), [200]);
+
+        // Redrawn part way through the fade, still full brightness.
+        assert_eq!(fade(&mut phosphor, &dark, 3), [100, 50, 25]);
+        assert_eq!(fade(&mut phosphor, &screen, 1), [200]);
+        // The pixels around it never lit up.
+        assert_eq!(phosphor.frame(&screen, 4, 2, &GREY)[..3], [[0; 3], [200; 3], [0; 3]]);
+
+        // A slower decay takes longer, 200 * 0.8^27 is the first under a half.
+        // None at all goes out at once.
+        let mut phosphor = Phosphor::new(0.8);
+        fade(&mut phosphor, &screen, 1);
+        let frames = fade(&mut phosphor, &dark, 40).iter().position(|&value| value == 0);
+        assert_eq!(frames, Some(26));
+        let mut phosphor = Phosphor::new(0.0);
+        fade(&mut phosphor, &screen, 1);
+        assert_eq!(fade(&mut phosphor, &dark, 1), [0]);
+    }
+}
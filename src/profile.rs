@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::callstack::Frame;
+use crate::emulator::{Emulator, MEMORY_SIZE};
+use crate::error::{EmuError, StepOutcome};
+use crate::instruction::{Address, Instruction};
+use crate::symbols::Symbols;
+
+/*
+Counts where instructions go. Every executed instruction is charged to its
+address and to the chain of subroutines that were active when it ran, the
+outermost being the ROM itself at 0x200. Instructions that only waited (FX0A
+without a key, a draw waiting for vblank) are not counted.
+
+`report` is the hot spot table, `folded` the `a;b;c count` lines that
+flamegraph.pl, inferno and speedscope read.
+*/
+
+const ROM_ENTRY: Address = 0x200;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Subroutine {
+    calls: u64,
+    own: u64,                   // Instructions run in the subroutine itself
+    total: u64,                 // ... and in everything it called
+}
+
+pub struct Profiler {
+    addresses: Vec<u64>,                        // Instructions run per address
+    subroutines: BTreeMap<Address, Subroutine>,
+    stacks: HashMap<Vec<Address>, u64>,         // Instructions run per chain of subroutine entries
+    chain: Vec<Address>,                        // The chain for the instruction being run
+    pc: Address,
+    instructions: u64,
+    frames: u64,
+    this_frame: u64,
+    fewest_per_frame: u64,
+    most_per_frame: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+
+    pub fn new() -> Profiler {
+        Profiler {
+            addresses: vec![0; MEMORY_SIZE],
+            subroutines: BTreeMap::new(),
+            stacks: HashMap::new(),
+            chain: Vec::new(),
+            pc: 0,
+            instructions: 0,
+            frames: 0,
+            this_frame: 0,
+            fewest_per_frame: u64::MAX,
+            most_per_frame: 0,
+        }
+    }
+
+    // Where the next instruction runs, called before it does.
+    pub(crate) fn begin(&mut self, pc: Address, frames: &[Frame]) {
+        self.pc = pc;
+        self.chain.clear();
+        self.chain.push(ROM_ENTRY);
+        self.chain.extend(frames.iter().map(|frame| frame.callee));
+    }
+
+    // Charge the instruction begin saw, if it did anything.
+    pub(crate) fn finish(&mut self, instruction: Option<Instruction>, result: &Result<StepOutcome, EmuError>) {
+        if !matches!(result, Ok(StepOutcome::Executed | StepOutcome::Exited | StepOutcome::Watchpoint)) {
+            return;
+        }
+        self.instructions += 1;
+        self.this_frame += 1;
+        self.addresses[self.pc as usize] += 1;
+
+        match self.stacks.get_mut(&self.chain[..]) {
+            Some(count) => *count += 1,
+            None => { self.stacks.insert(self.chain.clone(), 1); },
+        }
+        let innermost = self.chain.len() - 1;
+        for (depth, &entry) in self.chain.iter().enumerate() {
+            // Recursion shows up several times in the chain but only ran the instruction once.
+            if self.chain[..depth].contains(&entry) {
+                continue;
+            }
+            let subroutine = self.subroutines.entry(entry).or_default();
+            subroutine.total += 1;
+            if depth == innermost {
+                subroutine.own += 1;
+            }
+        }
+        if let Some(Instruction::Call(address)) = instruction {
+            self.subroutines.entry(address).or_default().calls += 1;
+        }
+    }
+
+    // The 60Hz tick, closes the count for this frame.
+    pub(crate) fn end_frame(&mut self) {
+        self.frames += 1;
+        self.fewest_per_frame = self.fewest_per_frame.min(self.this_frame);
+        self.most_per_frame = self.most_per_frame.max(self.this_frame);
+        self.this_frame = 0;
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // How often the instruction at `address` ran.
+    pub fn count(&self, address: Address) -> u64 {
+        self.addresses[address as usize]
+    }
+
+    // The `top` busiest addresses and every subroutine, busiest first:
+    //
+    //     1200 instructions over 60 frames, 20.0 per frame (min 18, max 22)
+    //
+    //      count      %  address
+    //        300  25.0%  0x20A  draw       LD I, 0x300
+    //
+    //      calls       own     total  subroutine
+    //         60       840      1200  draw
+    pub fn report(&self, emulator: &Emulator, symbols: &Symbols, top: usize) -> String {
+        let mut out = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let _ = write!(out, "{} instructions", self.instructions);
+        if self.frames > 0 {
+            let _ = write!(out, " over {} frames, {:.1} per frame (min {}, max {})",
+                self.frames, self.instructions as f64 / self.frames as f64, self.fewest_per_frame, self.most_per_frame);
+        }
+        let _ = writeln!(out);
+
+        let mut hot: Vec<(usize, u64)> = self.addresses.iter().copied().enumerate().filter(|&(_, count)| count > 0).collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\n {:>9}      %  address", "count");
+        for &(address, count) in hot.iter().take(top) {
+            let text = Instruction::decode(&emulator.memory, address).map_or("???".to_string(), |instruction| instruction.to_string());
+            let _ = writeln!(out, " {:>9} {:>5.1}%  0x{:03X}  {:<16} {}", count, percent(count), address, symbols.describe(address as Address), text);
+        }
+
+        let mut subroutines: Vec<(&Address, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+        let _ = writeln!(out, "\n {:>9} {:>9} {:>9}  subroutine", "calls", "own", "total");
+        for (&entry, subroutine) in subroutines {
+            let _ = writeln!(out, " {:>9} {:>9} {:>9}  {}", subroutine.calls, subroutine.own, subroutine.total, symbols.describe(entry));
+        }
+        out
+    }
+
+    // One `main;draw;sprite 120` line per chain of subroutines.
+    pub fn folded(&self, symbols: &Symbols) -> String {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(chain, count)| {
+                let names: Vec<String> = chain.iter().map(|&entry| symbols.describe(entry)).collect();
+                format!("{} {}", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        let mut out = lines.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+}
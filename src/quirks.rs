@@ -0,0 +1,94 @@
+// The original CHIP-8 spec leaves a handful of opcodes ambiguous and every
+// interpreter since the COSMAC VIP picked its own reading. ROMs are written
+// against one of them, so the emulator has to be told which one to mimic.
+
+// What FX55/FX65 do to I after the transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    Unchanged,  // SUPER-CHIP 1.1
+    X,          // CHIP-48, off by one
+    XPlusOne,   // COSMAC VIP
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    // How FX55/FX65 leave I.
+    pub index_increment: IndexIncrement,
+    // BNNN becomes BXNN and jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    // DXYN waits for the next vertical blank, so at most one draw per frame.
+    pub display_wait: bool,
+    // Sprites are cut off at the screen edge instead of wrapping around.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    // The original interpreter on the RCA COSMAC VIP.
+    pub const fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            display_wait: true,
+            clip_sprites: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators.
+    pub const fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::X,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: true,
+        }
+    }
+
+    // SUPER-CHIP 1.1, which most "SCHIP" games are written for.
+    pub const fn superchip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: true,
+        }
+    }
+
+    // What most modern interpreters and Octo programs expect.
+    pub const fn modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: false,
+        }
+    }
+
+    // Look a preset up by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" | "cosmac-vip" | "chip8" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::superchip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::modern()
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+use crate::savestate::Reader;
+
+// Ten seconds at 60 frames a second.
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+
+// Keeps the last few seconds of play so they can be stepped back through.
+//
+// Only the newest state is kept whole. Every older frame is stored as the
+// XOR between it and the frame after it, with runs of zeros squeezed out;
+// between two frames almost nothing changes, so most deltas are a few bytes.
+pub struct Rewind {
+    capacity: usize,
+    latest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::new(DEFAULT_REWIND_FRAMES)
+    }
+}
+
+impl Rewind {
+
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            latest: Vec::new(),
+            deltas: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // Snapshot the emulator, call this once per frame.
+    pub fn record(&mut self, emulator: &Emulator) {
+        let mut state = Vec::with_capacity(self.latest.len());
+        emulator.write_state(&mut state);
+
+        if self.latest.len() == state.len() {
+            self.deltas.push_back(encode_delta(&self.latest, &state));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        } else {
+            // First frame, start the history here.
+            self.deltas.clear();
+        }
+        self.latest = state;
+    }
+
+    // Go back one frame. Returns false once the history is used up.
+    pub fn step_back(&mut self, emulator: &mut Emulator) -> bool {
+        let delta = match self.deltas.pop_back() {
+            Some(delta) => delta,
+            None => return false,
+        };
+        apply_delta(&mut self.latest, &delta);
+        emulator
+            .read_state(&mut Reader { data: &self.latest })
+            .expect("rewind history holds complete states");
+        true
+    }
+
+    // Number of frames that can still be stepped back.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+    }
+}
+
+// XOR of two equally long states as (zero run, literal length, literal bytes)
+// records, lengths as LEB128.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < old.len() {
+        let zeros = old[pos..].iter().zip(&new[pos..]).take_while(|(a, b)| a == b).count();
+        pos += zeros;
+        let literal = old[pos..].iter().zip(&new[pos..]).take_while(|(a, b)| a != b).count();
+        push_len(&mut out, zeros);
+        push_len(&mut out, literal);
+        out.extend(old[pos..pos + literal].iter().zip(&new[pos..pos + literal]).map(|(a, b)| a ^ b));
+        pos += literal;
+    }
+    out
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut input = delta;
+    while !input.is_empty() {
+        pos += take_len(&mut input);
+        let literal = take_len(&mut input);
+        for (byte, xor) in state[pos..pos + literal].iter_mut().zip(&input[..literal]) {
+            *byte ^= xor;
+        }
+        input = &input[literal..];
+        pos += literal;
+    }
+}
+
+fn push_len(out: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let byte = (len & 0x7F) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn take_len(input: &mut &[u8]) -> usize {
+    let mut len = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        len |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn round_trip(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let delta = encode_delta(old, new);
+        let mut state = new.to_vec();
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, old);
+        delta
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let old: Vec<u8> = (0..1000).map(|index| (index * 13) as u8).collect();
+
+        // Nothing changed: one zero run over everything, 1000 as two LEB128 bytes.
+        assert_eq!(round_trip(&old, &old), [0xE8, 0x07, 0x00]);
+
+        let mut new = old.clone();
+        new[999] ^= 0x55;
+        assert_eq!(round_trip(&old, &new), [0xE7, 0x07, 0x01, 0x55]);
+
+        let mut new = old.clone();
+        new[0] ^= 0x01;
+        new[500] ^= 0xFF;
+        new[501] ^= 0xFF;
+        round_trip(&old, &new);
+
+        let new: Vec<u8> = old.iter().map(|byte| !byte).collect();
+        round_trip(&old, &new);
+        round_trip(&[], &[]);
+    }
+
+    #[test]
+    fn lengths_round_trip() {
+        for len in [0, 1, 127, 128, 16383, 16384, usize::MAX >> 1] {
+            let mut out = Vec::new();
+            push_len(&mut out, len);
+            let mut input = out.as_slice();
+            assert_eq!(take_len(&mut input), len);
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn stepping_back_restores_earlier_frames_exactly() {
+        // Count in V0, draw it and pick random numbers, so every frame differs.
+        const ROM: [u8; 14] = [0x70, 0x01, 0xF0, 0x29, 0x00, 0xE0, 0xD1, 0x15, 0xC2, 0xFF, 0x12, 0x00, 0x00, 0x00];
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.seed(1);
+        emulator.load_rom(&ROM).unwrap();
+        let mut rewind = Rewind::new(100);
+        let mut states = Vec::new();
+        for _ in 0..30 {
+            rewind.record(&emulator);
+            states.push(emulator.save_state());
+            emulator.run_frame(11).result.unwrap();
+        }
+        rewind.record(&emulator);
+
+        for expected in states.iter().rev().take(20) {
+            assert!(rewind.step_back(&mut emulator));
+            assert_eq!(&emulator.save_state(), expected);
+        }
+        assert_eq!(rewind.len(), 10);
+
+        // The numbers after the restored frame come out the same again.
+        let mut replay = Emulator::new(Quirks::default());
+        replay.load_rom(&ROM).unwrap();
+        replay.load_state(&states[10]).unwrap();
+        emulator.run_frame(11).result.unwrap();
+        replay.run_frame(11).result.unwrap();
+        assert_eq!(emulator.save_state(), replay.save_state());
+    }
+
+    #[test]
+    fn history_is_capped() {
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(5);
+        for _ in 0..20 {
+            rewind.record(&emulator);
+            emulator.run_frame(2).result.unwrap();
+        }
+        assert_eq!(rewind.len(), 5);
+        for _ in 0..5 {
+            assert!(rewind.step_back(&mut emulator));
+        }
+        assert!(!rewind.step_back(&mut emulator));
+        assert_eq!(emulator.v[0], 14);
+    }
+}
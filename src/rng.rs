@@ -0,0 +1,97 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+// Where CXNN gets its random bytes from. Swap it out for a fixed seed to get
+// reproducible runs, replays and tests.
+pub trait RandomSource: Send {
+    fn next_byte(&mut self) -> u8;
+
+    // The generator's state for save states and rewind, see restore. Sources
+    // that can't be saved return None and are kept as they are on load.
+    fn save(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+// Tags in front of a saved source's state.
+const SEEDED: u8 = 1;
+
+// Rebuild a source from what its save returned, None if the data is not one.
+pub(crate) fn restore(data: &[u8]) -> Option<Box<dyn RandomSource>> {
+    let (&tag, state) = data.split_first()?;
+    match tag {
+        SEEDED if state.len() == 32 + 8 + 16 => {
+            let mut rng = ChaCha12Rng::from_seed(state[..32].try_into().unwrap());
+            rng.set_stream(u64::from_le_bytes(state[32..40].try_into().unwrap()));
+            rng.set_word_pos(u128::from_le_bytes(state[40..].try_into().unwrap()));
+            Some(Box::new(SeededRandom { rng }))
+        },
+        _ => None,
+    }
+}
+
+// The default source. Seeded from the OS unless a seed is given.
+pub struct SeededRandom {
+    rng: ChaCha12Rng,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom { rng: ChaCha12Rng::seed_from_u64(seed) }
+    }
+
+    pub fn from_entropy() -> SeededRandom {
+        SeededRandom { rng: ChaCha12Rng::from_entropy() }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.rng.gen()
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        let mut out = vec![SEEDED];
+        out.extend_from_slice(&self.rng.get_seed());
+        out.extend_from_slice(&self.rng.get_stream().to_le_bytes());
+        out.extend_from_slice(&self.rng.get_word_pos().to_le_bytes());
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &mut dyn RandomSource, count: usize) -> Vec<u8> {
+        (0..count).map(|_| source.next_byte()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_numbers() {
+        assert_eq!(bytes(&mut SeededRandom::new(7), 100), bytes(&mut SeededRandom::new(7), 100));
+        assert_ne!(bytes(&mut SeededRandom::new(7), 100), bytes(&mut SeededRandom::new(8), 100));
+    }
+
+    #[test]
+    fn restored_sources_continue_where_they_were_saved() {
+        let sources: [Box<dyn RandomSource>; 2] = [
+            Box::new(SeededRandom::new(3)),
+            Box::new(SeededRandom::from_entropy()),
+        ];
+        for mut source in sources {
+            // Part way into a ChaCha block, so the word position matters.
+            bytes(source.as_mut(), 13);
+            let mut restored = restore(&source.save().unwrap()).unwrap();
+            assert_eq!(bytes(restored.as_mut(), 200), bytes(source.as_mut(), 200));
+        }
+    }
+
+    #[test]
+    fn bad_states_are_not_restored() {
+        let state = SeededRandom::new(3).save().unwrap();
+        assert!(restore(&[]).is_none());
+        assert!(restore(&state[..state.len() - 1]).is_none());
+        assert!(restore(&[99; 57]).is_none());
+    }
+}
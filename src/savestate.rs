@@ -0,0 +1,285 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::{Emulator, Screen, HIRES_HEIGHT, HIRES_WIDTH, MEMORY_SIZE};
+use crate::rng;
+
+/*
+Save state layout, all numbers little endian:
+|- "C8ST"           magic
+|- u16              format version
+|- u64              hash of the ROM the state was taken from
+|- u32 + bytes      memory
+|- ...              registers, stack, timers, display and the rest of the
+                    machine in the order of write_state below
+|- u16 + bytes      the CXNN generator's state, empty if it can't be saved
+*/
+
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const STATE_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    // Not a save state at all.
+    BadMagic,
+    // Written by a newer (or older, incompatible) build.
+    UnsupportedVersion(u16),
+    // Taken from a different ROM than the one that is loaded.
+    RomMismatch { expected: u64, found: u64 },
+    // Ran out of data before the state was complete.
+    Truncated,
+    // A value no machine can be in, like a stack pointer past the stack.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            StateError::RomMismatch { expected, found } => write!(f, "save state is for ROM {:016x}, loaded ROM is {:016x}", found, expected),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupt(what) => write!(f, "save state is corrupt: {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl Emulator {
+
+    // Snapshot everything the running program can observe.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + HIRES_WIDTH * HIRES_HEIGHT + 256);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        self.write_state(&mut out);
+        out
+    }
+
+    // Restore a snapshot from save_state. Nothing is touched unless the whole state is valid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { data };
+        if reader.bytes(4)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let found = reader.u64()?;
+        if found != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: self.rom_hash, found });
+        }
+
+        // Parse into a scratch machine first so a bad state leaves this one alone.
+        let state = reader.data;
+        Emulator::new(self.quirks).read_state(&mut Reader { data: state })?;
+        self.read_state(&mut Reader { data: state })
+    }
+
+    // The machine state without the header. Rewind diffs these directly.
+    pub(crate) fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        for address in self.stack {
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        out.push(self.sp);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        for row in self.display.iter() {
+            out.extend_from_slice(row);
+        }
+        out.push(self.hires as u8);
+        out.extend(self.keys.iter().map(|&key| key as u8));
+        out.extend_from_slice(&self.rpl);
+        out.push(self.planes);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.push(self.exited as u8);
+        out.push(self.vblank as u8);
+        let rng = self.rng.save().unwrap_or_default();
+        out.extend_from_slice(&(rng.len() as u16).to_le_bytes());
+        out.extend_from_slice(&rng);
+    }
+
+    pub(crate) fn read_state(&mut self, reader: &mut Reader) -> Result<(), StateError> {
+        let memory_len = reader.u32()? as usize;
+        if memory_len != MEMORY_SIZE {
+            return Err(StateError::Corrupt("wrong memory size"));
+        }
+        self.memory = reader.bytes(memory_len)?.to_vec();
+        self.v.copy_from_slice(reader.bytes(16)?);
+        self.i = reader.u16()?;
+        self.pc = reader.u16()?;
+        for address in self.stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        self.sp = reader.u8()?;
+        if self.sp as usize > self.stack.len() {
+            return Err(StateError::Corrupt("stack pointer past the stack"));
+        }
+        self.delay_timer = reader.u8()?;
+        self.sound_timer = reader.u8()?;
+        let mut display: Screen = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        for row in display.iter_mut() {
+            row.copy_from_slice(reader.bytes(HIRES_WIDTH)?);
+        }
+        self.display = display;
+        self.hires = reader.u8()? != 0;
+        for key in self.keys.iter_mut() {
+            *key = reader.u8()? != 0;
+        }
+        self.rpl.copy_from_slice(reader.bytes(16)?);
+        self.planes = reader.u8()?;
+        self.audio_pattern.copy_from_slice(reader.bytes(16)?);
+        self.pitch = reader.u8()?;
+        self.exited = reader.u8()? != 0;
+        self.vblank = reader.u8()? != 0;
+        let rng_len = reader.u16()? as usize;
+        let rng = reader.bytes(rng_len)?;
+        if !rng.is_empty() {
+            self.rng = rng::restore(rng).ok_or(StateError::Corrupt("unknown random number generator state"))?;
+        }
+        self.draw_flag = true;
+        self.sync_frames();
+        Ok(())
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+// Where save slot `slot` of a ROM lives: next to the ROM as game.ch8.1.state
+pub fn slot_path<P: AsRef<Path>>(rom: P, slot: u8) -> PathBuf {
+    let mut path = rom.as_ref().as_os_str().to_owned();
+    path.push(format!(".{}.state", slot));
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // Where the stack pointer sits in a save state: header, memory, V, I, pc and the stack.
+    const SP_OFFSET: usize = 4 + 2 + 8 + 4 + MEMORY_SIZE + 16 + 2 + 2 + 32;
+
+    // CALL 0x206, then add to V0 and draw in a loop
+    const ROM: [u8; 14] = [0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x70, 0x01, 0xA0, 0x50, 0xD0, 0x15, 0x12, 0x06];
+
+    fn running() -> Emulator {
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.seed(1);
+        emulator.load_rom(&ROM).unwrap();
+        for _ in 0..10 {
+            emulator.run_frame(7).result.unwrap();
+        }
+        emulator
+    }
+
+    #[test]
+    fn round_trip() {
+        let emulator = running();
+        let state = emulator.save_state();
+        let mut loaded = Emulator::new(Quirks::default());
+        loaded.load_rom(&ROM).unwrap();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.frames(), emulator.frames());
+    }
+
+    #[test]
+    fn truncated_states_are_rejected() {
+        let mut emulator = running();
+        let state = emulator.save_state();
+        for len in [0, 3, 10, SP_OFFSET, state.len() - 1] {
+            assert_eq!(emulator.load_state(&state[..len]), Err(StateError::Truncated));
+        }
+    }
+
+    #[test]
+    fn wrong_magic_and_version_are_rejected() {
+        let mut emulator = running();
+        let mut state = emulator.save_state();
+        state[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert_eq!(emulator.load_state(&state), Err(StateError::UnsupportedVersion(99)));
+        state[0] = b'X';
+        assert_eq!(emulator.load_state(&state), Err(StateError::BadMagic));
+    }
+
+    #[test]
+    fn corrupt_states_are_rejected_and_change_nothing() {
+        let mut emulator = running();
+        let before = emulator.save_state();
+
+        let mut state = before.clone();
+        state[SP_OFFSET] = 40;
+        assert!(matches!(emulator.load_state(&state), Err(StateError::Corrupt(_))));
+
+        let mut state = before[..14].to_vec();
+        state.extend_from_slice(&0u32.to_le_bytes());
+        state.extend_from_slice(&before[14 + 4 + MEMORY_SIZE..]);
+        assert!(matches!(emulator.load_state(&state), Err(StateError::Corrupt(_))));
+
+        assert_eq!(emulator.save_state(), before);
+    }
+
+    #[test]
+    fn loaded_states_replay_the_same_random_numbers() {
+        // V0 = rand, forever
+        const RANDOM: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+        let numbers = |emulator: &mut Emulator| -> Vec<u8> {
+            (0..50).map(|_| {
+                emulator.run_frame(2).result.unwrap();
+                emulator.v[0]
+            }).collect()
+        };
+
+        let mut emulator = Emulator::new(Quirks::default());
+        emulator.load_rom(&RANDOM).unwrap();
+        numbers(&mut emulator);
+        let state = emulator.save_state();
+        let first = numbers(&mut emulator);
+
+        // Another machine with its own entropy seeded generator.
+        let mut loaded = Emulator::new(Quirks::default());
+        loaded.load_rom(&RANDOM).unwrap();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(numbers(&mut loaded), first);
+    }
+}
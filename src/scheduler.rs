@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use crate::emulator::Emulator;
+use crate::error::{EmuError, StepOutcome};
+
+// Timers and the screen run at 60Hz no matter how fast the CPU is.
+pub const FRAME_RATE: u32 = 60;
+
+// A sensible default for most CHIP-8 games, about 660 instructions per second.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+
+// Paces the emulator in real time. Every frame runs a fixed budget of
+// instructions, ticks the timers once and then waits for the next frame.
+pub struct Scheduler {
+    instructions_per_second: f64,
+    owed: f64,                      // Fractional instructions carried over between frames
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+impl Scheduler {
+
+    // Absurd budgets are capped at u32::MAX instructions per second.
+    pub fn new(instructions_per_frame: u32) -> Scheduler {
+        Scheduler::with_hz(instructions_per_frame.saturating_mul(FRAME_RATE))
+    }
+
+    // Target a CPU speed in instructions per second instead. Speeds that are
+    // not a multiple of 60 spread the remainder over the frames.
+    pub fn with_hz(hz: u32) -> Scheduler {
+        Scheduler {
+            instructions_per_second: hz.max(1) as f64,
+            owed: 0.0,
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn hz(&self) -> u32 {
+        self.instructions_per_second as u32
+    }
+
+    // How many instructions the next frame gets. For frontends that pace
+    // frames themselves, like the debugger and the headless runner.
+    pub fn next_budget(&mut self) -> u32 {
+        self.owed += self.instructions_per_second / FRAME_RATE as f64;
+        let budget = self.owed.floor();
+        self.owed -= budget;
+        budget as u32
+    }
+
+    // Run the next frame without waiting for it.
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> Result<StepOutcome, EmuError> {
+        let budget = self.next_budget();
+        emulator.run_frame(budget).result
+    }
+
+    // Sleep until the next frame is due. If we fell far behind (a debugger
+    // pause, a slow present) start over instead of running frames back to back.
+    pub fn wait_for_next_frame(&mut self) {
+        self.next_frame += self.frame_duration;
+        let now = Instant::now();
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_duration * 4 {
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budgets(scheduler: &mut Scheduler, frames: usize) -> Vec<u32> {
+        (0..frames).map(|_| scheduler.next_budget()).collect()
+    }
+
+    #[test]
+    fn instructions_per_frame() {
+        let mut scheduler = Scheduler::new(11);
+        assert_eq!(scheduler.hz(), 660);
+        assert!(budgets(&mut scheduler, 120).iter().all(|&budget| budget == 11));
+    }
+
+    #[test]
+    fn remainders_are_spread_over_the_frames() {
+        let mut scheduler = Scheduler::with_hz(1000);
+        let budgets = budgets(&mut scheduler, 600);
+        assert!(budgets.iter().all(|&budget| budget == 16 || budget == 17));
+        for second in budgets.chunks(60) {
+            assert!((999..=1001).contains(&second.iter().sum::<u32>()));
+        }
+        assert_eq!(budgets.iter().sum::<u32>(), 10_000);
+    }
+
+    #[test]
+    fn slow_speeds_run_some_frames_empty() {
+        let mut scheduler = Scheduler::with_hz(30);
+        assert_eq!(budgets(&mut scheduler, 4), [0, 1, 0, 1]);
+        let mut scheduler = Scheduler::with_hz(0);
+        assert_eq!(budgets(&mut scheduler, 60).iter().sum::<u32>(), 1);
+    }
+
+    #[test]
+    fn huge_budgets_saturate() {
+        let mut scheduler = Scheduler::new(u32::MAX);
+        assert_eq!(scheduler.hz(), u32::MAX);
+        assert_eq!(scheduler.next_budget(), u32::MAX / FRAME_RATE);
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+
+use crate::asm::parse_number;
+use crate::instruction::Address;
+
+/*
+Symbol files map labels to addresses, one per line, as written by
+`chip8 octo --labels`:
+
+    0x202 main
+    0x2A0 draw_player       # comments start with # or ;
+
+Decimal addresses work too.
+*/
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    by_name: HashMap<String, Address>,
+    by_address: BTreeMap<Address, String>,
+}
+
+impl Symbols {
+
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Symbols> {
+        Symbols::parse(&std::fs::read_to_string(path)?)
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+
+    // Errors name the line that could not be read.
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(char::is_whitespace)
+                .and_then(|(address, name)| Some((parse_address(address)?, name.trim())));
+            match parsed {
+                Some((address, name)) => symbols.insert(name, address),
+                None => return Err(format!("line {}: expected `<address> <label>`", index + 1)),
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, name: &str, address: Address) {
+        self.by_name.insert(name.to_string(), address);
+        // Several labels on one address: keep the first for naming it.
+        self.by_address.entry(address).or_insert_with(|| name.to_string());
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<Address> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // The closest label at or before `address` and how far past it we are.
+    pub fn lookup(&self, address: Address) -> Option<(&str, Address)> {
+        self.by_address.range(..=address).next_back()
+            .map(|(&label, name)| (name.as_str(), address - label))
+    }
+
+    // `main`, `main+4`, or plain `0x20A` with no label before it.
+    pub fn describe(&self, address: Address) -> String {
+        match self.lookup(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    // A label name, or an address like `0x2A0` or `672`.
+    pub fn resolve(&self, text: &str) -> Option<Address> {
+        if let Some(address) = self.address_of(text) {
+            return Some(address);
+        }
+        parse_address(text)
+    }
+}
+
+fn parse_address(text: &str) -> Option<Address> {
+    parse_number(text).and_then(|address| Address::try_from(address).ok())
+}
+
+impl<'a> FromIterator<(&'a String, &'a Address)> for Symbols {
+    fn from_iter<T: IntoIterator<Item = (&'a String, &'a Address)>>(labels: T) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, &address) in labels {
+            symbols.insert(name, address);
+        }
+        symbols
+    }
+}
@@ -0,0 +1,237 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::emulator::{Emulator, Screen};
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+
+/*
+The terminal frontend. Draws the framebuffer in place on the alternate
+screen and reads the keypad from raw mode keyboard input, so it runs over
+SSH without a display server.
+
+Most terminals only send key presses, never releases. Where the terminal
+speaks the kitty keyboard protocol real releases are used, everywhere else a
+key counts as held for a few frames after its last press; the terminal's key
+repeat keeps a key held down pressed.
+*/
+
+// How long a key stays down after its last press when the terminal
+// can't tell us it was released. Key repeat refreshes it well within this.
+const HOLD_FRAMES: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    // ▀ with the top pixel as foreground and the bottom one as background,
+    // one character per 1x2 pixels, in colour.
+    HalfBlock,
+    // One braille character per 2x4 pixels, for small terminals.
+    Braille,
+}
+
+impl Glyphs {
+    pub fn from_name(name: &str) -> Option<Glyphs> {
+        match name {
+            "half" => Some(Glyphs::HalfBlock),
+            "braille" => Some(Glyphs::Braille),
+            _ => None,
+        }
+    }
+}
+
+pub struct Terminal {
+    out: io::Stdout,
+    glyphs: Glyphs,
+    palette: Palette,
+    phosphor: Option<Phosphor>,     // Fades pixels out in half block mode
+    last_frame: String,         // Skip redrawing frames that did not change
+    releases: bool,             // The terminal reports key releases
+    held: [u32; 16],            // Frames left before an emulated release
+}
+
+impl Terminal {
+
+    // Switch to raw mode on the alternate screen, undone on drop.
+    pub fn new(glyphs: Glyphs, palette: Palette) -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Terminal { out, glyphs, palette, phosphor: None, last_frame: String::new(), releases, held: [0; 16] })
+    }
+
+    // Turn persistence on, or off with None. Braille has no colours to fade.
+    pub fn set_phosphor(&mut self, phosphor: Option<Phosphor>) {
+        self.phosphor = phosphor;
+    }
+
+    // Draw the top left width x height of the framebuffer.
+    pub fn draw_screen(&mut self, screen: &Screen, width: usize, height: usize) -> io::Result<()> {
+        let frame = match self.glyphs {
+            Glyphs::HalfBlock => {
+                let colors = match self.phosphor.as_mut() {
+                    Some(phosphor) => phosphor.frame(screen, width, height, &self.palette),
+                    None => screen.iter().take(height).flat_map(|row| row.iter().take(width).map(|&pixel| self.palette.color(pixel))).collect(),
+                };
+                half_blocks(&colors, width)
+            },
+            Glyphs::Braille => self.braille(screen, width, height),
+        };
+        if frame != self.last_frame {
+            queue!(self.out, MoveTo(0, 0), Print(&frame), ResetColor)?;
+            self.out.flush()?;
+            self.last_frame = frame;
+        }
+        Ok(())
+    }
+
+    fn braille(&self, screen: &Screen, width: usize, height: usize) -> String {
+        // Dot bits of a braille character, by row then column.
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        let mut frame = String::new();
+        let mut color = None;
+        for (row, lines) in screen[..height].chunks(4).enumerate() {
+            if row > 0 {
+                frame.push_str("\r\n");
+            }
+            for x in (0..width).step_by(2) {
+                let mut dots = 0;
+                let mut brightest = 0;
+                for (line, bits) in lines.iter().zip(DOTS) {
+                    for (dx, bit) in bits.into_iter().enumerate() {
+                        let pixel = line.get(x + dx).copied().unwrap_or(0);
+                        if pixel != 0 {
+                            dots |= bit;
+                            brightest = brightest.max(pixel);
+                        }
+                    }
+                }
+                if dots != 0 && color != Some(brightest) {
+                    frame.push_str(&format!("{}", SetForegroundColor(self.color(brightest))));
+                    color = Some(brightest);
+                }
+                frame.push(char::from_u32(0x2800 + dots).unwrap_or(' '));
+            }
+        }
+        frame
+    }
+
+    fn color(&self, pixel: u8) -> Color {
+        let [r, g, b] = self.palette.color(pixel);
+        Color::Rgb { r, g, b }
+    }
+
+    // Read the keyboard without blocking, once per frame. Returns false when
+    // the user asked to quit with Escape or Ctrl-C.
+    pub fn poll_keys(&mut self, emulator: &mut Emulator) -> io::Result<bool> {
+        if !self.releases {
+            for (key, frames) in self.held.iter_mut().enumerate() {
+                if *frames > 0 {
+                    *frames -= 1;
+                    if *frames == 0 {
+                        emulator.key_up(key as u8);
+                    }
+                }
+            }
+        }
+
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => return Ok(false),
+                Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers, .. }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+                Event::Key(KeyEvent { code: KeyCode::Char(c), kind, .. }) => {
+                    let key = match map_keys(c) {
+                        Some(key) => key,
+                        None => continue,
+                    };
+                    match kind {
+                        KeyEventKind::Release => emulator.key_up(key),
+                        _ => {
+                            emulator.key_down(key);
+                            self.held[key as usize] = HOLD_FRAMES;
+                        },
+                    }
+                },
+                // Start over on a clean screen.
+                Event::Resize(..) => {
+                    execute!(self.out, ResetColor, Clear(ClearType::All))?;
+                    self.last_frame.clear();
+                },
+                _ => {},
+            }
+        }
+        Ok(true)
+    }
+}
+
+// ▀ in the top pixel's colour on the bottom one's, from one colour per pixel row by row.
+fn half_blocks(colors: &[[u8; 3]], width: usize) -> String {
+    let rgb = |[r, g, b]: [u8; 3]| Color::Rgb { r, g, b };
+    let mut frame = String::new();
+    let mut current = None;
+    for (row, pair) in colors.chunks(width * 2).enumerate() {
+        if row > 0 {
+            frame.push_str("\r\n");
+        }
+        let (top, bottom) = pair.split_at(width.min(pair.len()));
+        for x in 0..width {
+            let top = rgb(top[x]);
+            let bottom = rgb(bottom.get(x).copied().unwrap_or([0; 3]));
+            // Only change colours when they do, it keeps the frame small.
+            if current != Some((top, bottom)) {
+                frame.push_str(&format!("{}{}", SetForegroundColor(top), SetBackgroundColor(bottom)));
+                current = Some((top, bottom));
+            }
+            frame.push('▀');
+        }
+    }
+    frame
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// The same layout as the window frontend:
+//
+//     1 2 3 4        1 2 3 C
+//     q w e r        4 5 6 D
+//     a s d f   ->   7 8 9 E
+//     z x c v        A 0 B F
+pub fn map_keys(key: char) -> Option<u8> {
+    match key.to_ascii_lowercase() {
+        '1' => Some(0x1),
+        '2' => Some(0x2),
+        '3' => Some(0x3),
+        '4' => Some(0xC),
+        'q' => Some(0x4),
+        'w' => Some(0x5),
+        'e' => Some(0x6),
+        'r' => Some(0xD),
+        'a' => Some(0x7),
+        's' => Some(0x8),
+        'd' => Some(0x9),
+        'f' => Some(0xE),
+        'z' => Some(0xA),
+        'x' => Some(0x0),
+        'c' => Some(0xB),
+        'v' => Some(0xF),
+        _ => None,
+    }
+}
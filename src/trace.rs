@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use crate::emulator::Emulator;
+use crate::error::{EmuError, StepOutcome};
+use crate::instruction::Instruction;
+
+/*
+One line per executed instruction:
+
+    0202 6105 V1=05 ; LD V1, 0x05
+    0204 8014 V0=06 VF=00 ; ADD V0, V1
+    0206 2300 SP=1 ; CALL 0x300
+
+pc and the raw opcode, then every register, I or SP the instruction changed,
+then the mnemonic after a `;`. Everything before the `;` only depends on the
+machine, so `cut -d';' -f1` of two emulators' traces diffs line by line.
+Instructions that wait (FX0A without a key, a draw waiting for vblank) did
+nothing and are left out.
+*/
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    ranges: Vec<(u16, u16)>,        // Only trace pc in one of these, inclusive. Empty traces everything.
+}
+
+// What the trace compares before and after a step.
+pub(crate) struct Registers {
+    v: [u8; 16],
+    i: u16,
+    sp: u8,
+}
+
+impl Registers {
+    pub(crate) fn of(emulator: &Emulator) -> Registers {
+        Registers { v: emulator.v, i: emulator.i, sp: emulator.sp }
+    }
+}
+
+impl Tracer {
+
+    pub fn new<W: Write + Send + 'static>(out: W) -> Tracer {
+        Tracer { out: Box::new(out), ranges: Vec::new() }
+    }
+
+    // Restrict the trace to pc in start..=end. Can be given more than once.
+    pub fn with_range(mut self, start: u16, end: u16) -> Tracer {
+        self.ranges.push((start, end));
+        self
+    }
+
+    pub(crate) fn wants(&self, pc: u16) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|&(start, end)| (start..=end).contains(&pc))
+    }
+
+    // Write errors are ignored, a trace is a debugging aid and should never stop the program.
+    pub(crate) fn record(&mut self, pc: u16, opcode: &[u8], instruction: Option<Instruction>, before: &Registers, after: &Registers, result: &Result<StepOutcome, EmuError>) {
+        if matches!(result, Ok(StepOutcome::WaitingForKey | StepOutcome::WaitingForVBlank)) {
+            return;
+        }
+        let mut line = format!("{:04X} ", pc);
+        for byte in opcode {
+            line.push_str(&format!("{:02X}", byte));
+        }
+        for (register, (old, new)) in before.v.iter().zip(&after.v).enumerate() {
+            if old != new {
+                line.push_str(&format!(" V{:X}={:02X}", register, new));
+            }
+        }
+        if before.i != after.i {
+            line.push_str(&format!(" I={:04X}", after.i));
+        }
+        if before.sp != after.sp {
+            line.push_str(&format!(" SP={:X}", after.sp));
+        }
+        match (instruction, result) {
+            (_, Err(error)) => line.push_str(&format!(" ; error: {}", error)),
+            (Some(instruction), _) => line.push_str(&format!(" ; {}", instruction)),
+            (None, _) => {},
+        }
+        let _ = writeln!(self.out, "{}", line);
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::asm::parse_number;
+use crate::instruction::{Instruction, Register};
+
+// Everything an instruction can write that a watchpoint can look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Memory(usize),
+    Register(Register),
+    Index,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Memory(address) => write!(f, "0x{:04X}", address),
+            Location::Register(register) => write!(f, "V{:X}", register),
+            Location::Index => write!(f, "I"),
+        }
+    }
+}
+
+// Stops execution after any instruction that writes to it, whether or not
+// the value changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    // start to end inclusive
+    Memory { start: usize, end: usize },
+    Register(Register),
+    Index,
+}
+
+impl Watch {
+    pub fn matches(&self, location: Location) -> bool {
+        match (*self, location) {
+            (Watch::Memory { start, end }, Location::Memory(address)) => (start..=end).contains(&address),
+            (Watch::Register(watched), Location::Register(register)) => watched == register,
+            (Watch::Index, Location::Index) => true,
+            _ => false,
+        }
+    }
+
+    // `V3`, `I`, `0x300` or `0x300-0x30F`.
+    pub fn from_name(name: &str) -> Option<Watch> {
+        let upper = name.to_ascii_uppercase();
+        if upper == "I" {
+            return Some(Watch::Index);
+        }
+        if let Some(digit) = upper.strip_prefix('V') {
+            return match usize::from_str_radix(digit, 16) {
+                Ok(register) if digit.len() == 1 => Some(Watch::Register(register)),
+                _ => None,
+            };
+        }
+        let address = |text: &str| parse_number(text.trim()).and_then(|address| usize::try_from(address).ok());
+        match upper.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (address(start)?, address(end)?);
+                if start <= end { Some(Watch::Memory { start, end }) } else { None }
+            },
+            None => address(&upper).map(|start| Watch::Memory { start, end: start }),
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Memory { start, end } if start == end => write!(f, "0x{:04X}", start),
+            Watch::Memory { start, end } => write!(f, "0x{:04X}-0x{:04X}", start, end),
+            Watch::Register(register) => write!(f, "V{:X}", register),
+            Watch::Index => write!(f, "I"),
+        }
+    }
+}
+
+// One write that hit a watchpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub instruction: Option<Instruction>,
+    pub location: Location,
+    pub old: u16,
+    pub new: u16,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = if self.location == Location::Index { 4 } else { 2 };
+        write!(f, "{}: 0x{:0w$X} -> 0x{:0w$X} at 0x{:03X}", self.location, self.old, self.new, self.pc, w = width)?;
+        if let Some(instruction) = self.instruction {
+            write!(f, " by {}", instruction)?;
+        }
+        Ok(())
+    }
+}